@@ -1,44 +1,287 @@
-use crate::args::Args;
-use anyhow::Result;
-use reqwest::{
-    blocking::{Client, Response},
-    header,
-};
+use crate::{args::Args, unix_socket};
+use anyhow::{bail, Context, Result};
+use reqwest::{blocking::Client, header, Certificate, StatusCode};
+use serde::de::DeserializeOwned;
+use std::{error, fmt, fs, path::PathBuf, time::Duration};
+use url::{Host, Url};
+
+/// Exit code used when Syncthing rejects the API key, so supervisors can tell
+/// it apart from Syncthing being unreachable.
+pub const UNAUTHORIZED_EXIT_CODE: i32 = 2;
+
+/// Syncthing answered 401 or 403, which no retry is going to fix.
+#[derive(Debug)]
+pub struct Unauthorized {
+    status: StatusCode,
+    path: String,
+}
+
+impl fmt::Display for Unauthorized {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Syncthing API returned {} for {}, check your API key",
+            self.status, self.path
+        )
+    }
+}
+
+impl error::Error for Unauthorized {}
+
+/// The requests `Runner` makes, so that tests can answer them from memory.
+pub trait SyncthingApi: fmt::Debug + Send + Sync {
+    fn get(&self, path: &str) -> Result<Response>;
+
+    /// A request that Syncthing may hold open for up to `wait`.
+    fn long_poll(&self, path: &str, wait: Duration) -> Result<Response>;
+
+    /// Drops every pooled connection, which may have died silently while the
+    /// machine was suspended.
+    fn reconnect(&mut self) -> Result<()>;
+
+    fn base_url(&self) -> &str;
+}
 
 #[derive(Debug)]
 pub struct ApiClient {
-    client: Client,
+    transport: Transport,
     base_url: String,
+    timeout: Duration,
+    // What `reconnect` builds a fresh client from.
+    headers: header::HeaderMap,
+    insecure: bool,
+    ca_cert: Option<Certificate>,
 }
 
-impl ApiClient {
-    pub fn new(args: &Args) -> Result<Self> {
-        Ok(Self {
-            client: ApiClient::build_client(args)?,
-            base_url: args.base_url.clone(),
+#[derive(Debug)]
+enum Transport {
+    Http(Client),
+    /// `unix:///path/to/socket`, which reqwest cannot connect to.
+    Unix(PathBuf),
+}
+
+#[derive(Debug)]
+pub enum Response {
+    Http(reqwest::blocking::Response),
+    Unix(unix_socket::Response),
+}
+
+impl Response {
+    pub fn json<T: DeserializeOwned>(self) -> Result<T> {
+        Ok(match self {
+            Response::Http(response) => response.json()?,
+            Response::Unix(response) => {
+                serde_json::from_slice(&response.body).map_err(unix_socket::Error::Decode)?
+            }
         })
     }
 
-    pub fn get(&self, path: &str) -> Result<Response> {
-        self.client
-            .get(format!("{}/{}", self.base_url, path))
-            .send()
-            .map_err(Into::into)
+    fn status(&self) -> StatusCode {
+        match self {
+            Response::Http(response) => response.status(),
+            Response::Unix(response) => response.status,
+        }
+    }
+
+    fn content_type(&self) -> Option<&str> {
+        match self {
+            Response::Http(response) => response
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok()),
+            Response::Unix(response) => response.content_type.as_deref(),
+        }
+    }
+
+    fn error_for_status(self) -> Result<Self> {
+        match self {
+            Response::Http(response) => Ok(Response::Http(response.error_for_status()?)),
+            Response::Unix(response)
+                if response.status.is_client_error() || response.status.is_server_error() =>
+            {
+                Err(unix_socket::Error::Status(response.status).into())
+            }
+            response => Ok(response),
+        }
+    }
+}
+
+impl SyncthingApi for ApiClient {
+    fn get(&self, path: &str) -> Result<Response> {
+        self.send(path, self.timeout)
+    }
+
+    fn long_poll(&self, path: &str, wait: Duration) -> Result<Response> {
+        self.send(path, wait + self.timeout)
+    }
+
+    fn reconnect(&mut self) -> Result<()> {
+        // Requests over the socket never reuse a connection.
+        if let Transport::Http(client) = &mut self.transport {
+            *client = ApiClient::build_client(&self.headers, self.insecure, self.ca_cert.as_ref())?;
+        }
+        Ok(())
+    }
+
+    fn base_url(&self) -> &str {
+        &self.base_url
     }
+}
+
+impl ApiClient {
+    /// `api_key` is the key itself, as resolved by `Args::load`.
+    pub fn new(args: &Args, base_url: &str, api_key: &str) -> Result<Self> {
+        let base_url = ApiClient::normalize_base_url(base_url)?;
+        if args.require_tls {
+            ApiClient::ensure_tls(&base_url)?;
+        }
 
-    fn build_client(args: &Args) -> Result<Client> {
         let mut headers = header::HeaderMap::new();
-        let mut auth_value = header::HeaderValue::from_str(&format!(
-            "Bearer {}",
-            Args::parse_secret(&args.api_key)?
-        ))?;
+        let mut auth_value = header::HeaderValue::from_str(&format!("Bearer {}", api_key))?;
         auth_value.set_sensitive(true);
         headers.insert(header::AUTHORIZATION, auth_value);
 
-        Client::builder()
-            .default_headers(headers)
+        let ca_cert = match &args.ca_cert {
+            Some(path) => {
+                let pem = fs::read(path)
+                    .with_context(|| format!("Failed to read CA certificate {}", path.display()))?;
+                Some(Certificate::from_pem(&pem)?)
+            }
+            None => None,
+        };
+
+        let transport = match base_url.strip_prefix("unix://") {
+            Some(socket) => Transport::Unix(PathBuf::from(socket)),
+            None => Transport::Http(ApiClient::build_client(
+                &headers,
+                args.insecure,
+                ca_cert.as_ref(),
+            )?),
+        };
+
+        Ok(Self {
+            transport,
+            base_url,
+            timeout: args.timeout_secs,
+            headers,
+            insecure: args.insecure,
+            ca_cert,
+        })
+    }
+
+    fn send(&self, path: &str, timeout: Duration) -> Result<Response> {
+        log::debug!("GET {}/{}", self.base_url, path);
+        let response = match &self.transport {
+            Transport::Http(client) => Response::Http(
+                client
+                    .get(format!("{}/{}", self.base_url, path))
+                    .timeout(timeout)
+                    .send()?,
+            ),
+            Transport::Unix(socket) => {
+                Response::Unix(unix_socket::get(socket, path, &self.headers, timeout)?)
+            }
+        };
+
+        // Fail here rather than on parsing whatever body came with it.
+        let status = response.status();
+        if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+            return Err(Unauthorized {
+                status,
+                path: path.to_string(),
+            }
+            .into());
+        }
+
+        let is_html = response
+            .content_type()
+            .is_some_and(|value| value.starts_with("text/html"));
+        if is_html {
+            bail!(
+                "Syncthing answered {} with an HTML page instead of JSON, \
+                 the API key is probably missing or wrong",
+                path
+            );
+        }
+
+        response.error_for_status()
+    }
+
+    /// Checks that the base URL is an http(s) or unix socket URL and puts it in the form the
+    /// request paths are appended to: `localhost:8384` becomes
+    /// `http://localhost:8384`, and trailing slashes are dropped.
+    pub fn normalize_base_url(base_url: &str) -> Result<String> {
+        let base_url = base_url.trim().trim_end_matches('/');
+        let base_url = if base_url.contains("://") {
+            base_url.to_string()
+        } else {
+            format!("http://{}", base_url)
+        };
+
+        if let Some(socket) = base_url.strip_prefix("unix://") {
+            if !socket.starts_with('/') {
+                bail!(
+                    "Invalid --base-url {}, expected unix:///path/to/socket",
+                    base_url
+                );
+            }
+            return Ok(base_url);
+        }
+
+        let url =
+            Url::parse(&base_url).with_context(|| format!("Invalid --base-url {}", base_url))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            bail!(
+                "Invalid --base-url {}, expected an http://, https:// or unix:// address",
+                base_url
+            );
+        }
+        if url.host().is_none() {
+            bail!("Invalid --base-url {}, no host given", base_url);
+        }
+
+        Ok(base_url)
+    }
+
+    /// Refuses to send the API key in plaintext to anything but this machine.
+    fn ensure_tls(base_url: &str) -> Result<()> {
+        let url = Url::parse(base_url)?;
+        if url.scheme() == "https" || url.scheme() == "unix" {
+            return Ok(());
+        }
+
+        let is_loopback = match url.host() {
+            Some(Host::Domain(domain)) => domain == "localhost",
+            Some(Host::Ipv4(ip)) => ip.is_loopback(),
+            Some(Host::Ipv6(ip)) => ip.is_loopback(),
+            None => false,
+        };
+        if !is_loopback {
+            bail!(
+                "Refusing to send the API key over plaintext {} to {}, use https:// or drop --require-tls",
+                url.scheme(),
+                base_url
+            );
+        }
+
+        Ok(())
+    }
+
+    fn build_client(
+        headers: &header::HeaderMap,
+        insecure: bool,
+        ca_cert: Option<&Certificate>,
+    ) -> Result<Client> {
+        let mut builder = Client::builder()
+            .default_headers(headers.clone())
+            // Every request sets its own timeout, long-polls need a longer one.
             .timeout(None)
-            .build()
-            .map_err(Into::into)
+            // Syncthing generates a self-signed certificate for its GUI.
+            .danger_accept_invalid_certs(insecure);
+        if let Some(cert) = ca_cert {
+            builder = builder.add_root_certificate(cert.clone());
+        }
+
+        builder.build().map_err(Into::into)
     }
 }
@@ -1,27 +1,694 @@
-use anyhow::Result;
-use clap::Parser;
-use std::{fs, path::Path};
+use crate::{
+    config_file::{self, Value},
+    env_file,
+    syncthing_config::GuiConfig,
+    template::Template,
+};
+use anyhow::{bail, Context, Result};
+use clap::{
+    builder::{FalseyValueParser, RangedU64ValueParser},
+    error::ErrorKind,
+    parser::ValueSource,
+    ArgAction, CommandFactory, FromArgMatches, Parser, ValueEnum,
+};
+use std::{
+    env,
+    ffi::OsString,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::Duration,
+};
 
-#[derive(Parser, Debug)]
+/// The crate version with the commit and date it was built from.
+const VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("WAYBAR_SYNCTHING_COMMIT"),
+    ", built ",
+    env!("WAYBAR_SYNCTHING_BUILD_DATE"),
+    ")"
+);
+
+/// `--version` also lists the Syncthing API endpoints, for bug reports.
+const LONG_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("WAYBAR_SYNCTHING_COMMIT"),
+    ", built ",
+    env!("WAYBAR_SYNCTHING_BUILD_DATE"),
+    ")\n",
+    "Syncthing API: rest/events, rest/events/disk, rest/db/completion, rest/db/status, ",
+    "rest/system/config, rest/system/connections, rest/system/status"
+);
+
+#[derive(Parser, Debug, Clone)]
+#[command(version = VERSION, long_version = LONG_VERSION)]
 pub struct Args {
-    #[arg(short, long, required = true, env = "SYNCTHING_API_KEY")]
-    pub api_key: String,
+    /// The API key, a file holding it, or `-` to read it from stdin; once for
+    /// every --base-url, or once for all of them
+    #[arg(
+        short,
+        long,
+        required_unless_present_any = ["list_events", "syncthing_config", "api_key_cmd"],
+        env = "SYNCTHING_API_KEY"
+    )]
+    pub api_key: Vec<String>,
+
+    /// Shell command printing the API key, used instead of --api-key
+    #[arg(long, value_name = "COMMAND")]
+    pub api_key_cmd: Option<String>,
 
+    /// Syncthing's address or unix:///path of its socket, repeated to show
+    /// several instances as one module
     #[arg(
         short,
         long,
         default_value = "http://localhost:8384",
         env = "SYNCTHING_BASE_URL"
     )]
-    pub base_url: String,
+    pub base_url: Vec<String>,
+
+    /// Where each instance's address goes in the tooltip with several --base-url
+    #[arg(long, value_enum, default_value_t = InstanceLabel::Grouped)]
+    pub combine_instances_label: InstanceLabel,
+
+    /// Load environment variables such as SYNCTHING_API_KEY from a dotenv file
+    #[arg(long, value_name = "PATH")]
+    pub env_file: Option<PathBuf>,
+
+    /// Take the API key and GUI address from Syncthing's config.xml
+    #[arg(long, value_name = "PATH", env = "SYNCTHING_CONFIG")]
+    pub syncthing_config: Option<PathBuf>,
+
+    /// Exit with code 3 when no request succeeded for this many seconds
+    #[arg(long, value_name = "SECS", value_parser = parse_secs)]
+    pub hang_timeout: Option<Duration>,
+
+    /// Give up on a request after this many seconds, on top of the long-poll wait
+    #[arg(long, value_name = "SECS", default_value = "30", value_parser = parse_secs)]
+    pub timeout_secs: Duration,
+
+    /// Text to show while Syncthing cannot be reached, with the `disconnected` class
+    #[arg(long, value_name = "TEXT")]
+    pub disconnected_text: Option<String>,
+
+    /// Refuse plaintext http:// base URLs unless they point at this machine
+    #[arg(long)]
+    pub require_tls: bool,
+
+    /// Accept any TLS certificate, such as the self-signed one Syncthing generates
+    #[arg(long)]
+    pub insecure: bool,
+
+    /// Trust this PEM certificate in addition to the system roots
+    #[arg(long, value_name = "PATH")]
+    pub ca_cert: Option<PathBuf>,
+
+    /// Truncate the text to this many display columns, ending with an ellipsis
+    #[arg(long, value_name = "N")]
+    pub text_max_length: Option<usize>,
+
+    /// Show one segment per folder in the text instead of the overall progress
+    #[arg(long)]
+    pub per_folder_text: bool,
+
+    /// Show at most this many segments in the text, followed by `+K more`
+    #[arg(long, value_name = "N")]
+    pub max_segments: Option<usize>,
+
+    /// Group progress by folder across all devices instead of per device
+    #[arg(long)]
+    pub cluster_view: bool,
+
+    /// Show one overall percentage with the size left
+    #[arg(long)]
+    pub progress_bar: bool,
+
+    /// Show one percentage for all folders on all devices, including synced ones
+    #[arg(long)]
+    pub show_global_progress: bool,
+
+    /// How the completion of several folders is combined into one
+    #[arg(long, value_enum, default_value_t = AggregateMode::Weighted)]
+    pub aggregate: AggregateMode,
+
+    /// Whose completion to show: every remote device's, or this device's own
+    /// against the cluster
+    #[arg(long, value_enum, default_value_t = Mode::Remote)]
+    pub mode: Mode,
+
+    /// Also show how many devices are fully synced, e.g. `4/5 synced`
+    #[arg(long)]
+    pub show_synced_ratio: bool,
+
+    /// Also show how many devices are connected, e.g. `2/3 online`
+    #[arg(long)]
+    pub show_connected: bool,
+
+    /// Keep the progress of devices that disconnect, marked in the tooltip
+    #[arg(long)]
+    pub keep_disconnected: bool,
+
+    /// Show `syncthing paused` with the `paused` class when all remote devices are paused
+    #[arg(long)]
+    pub show_when_paused_globally: bool,
+
+    /// Count devices and folders waiting to be accepted in the text, with the
+    /// `attention` class
+    #[arg(long)]
+    pub show_pending_approvals: bool,
+
+    /// Only show the overall percentage while syncing, without sizes
+    #[arg(long)]
+    pub minimal: bool,
+
+    /// Show counts of folders downloading, uploading and scanning instead of progress
+    #[arg(long)]
+    pub bucketed_text: bool,
+
+    /// Also show folders this device is scanning or pulling
+    #[arg(long)]
+    pub local_progress: bool,
+
+    /// Icon in front of every figure in the text
+    #[arg(long, default_value = "\u{f2f1}")]
+    pub icon: String,
+
+    /// Label the figures `Syncthing:` instead of with an icon, for fonts
+    /// without Nerd Font glyphs
+    #[arg(long, env = "SYNCTHING_NO_ICONS", value_parser = FalseyValueParser::new())]
+    pub no_icons: bool,
+
+    /// Text to show when nothing is syncing, instead of hiding the module
+    #[arg(long, value_name = "TEXT")]
+    pub idle_icon: Option<String>,
+
+    /// Text to show when nothing is syncing, e.g. `✓ synced {ago}`; `{ago}` is
+    /// left out until a folder has been seen getting in sync
+    #[arg(long, value_name = "TEMPLATE", value_parser = Template::parse_idle)]
+    pub idle_format: Option<Template>,
+
+    /// Set class `scanning` while a folder is scanning and nothing is transferring
+    #[arg(long)]
+    pub show_scanning: bool,
+
+    /// Text to show while a folder is scanning and nothing is transferring (with --show-scanning)
+    #[arg(long, value_name = "TEXT")]
+    pub scanning_icon: Option<String>,
+
+    /// Text for each pending folder, e.g. `{device} {percent}%`, joined by ` | `
+    #[arg(long, value_name = "TEMPLATE", value_parser = Template::parse)]
+    pub text_format: Option<Template>,
+
+    /// Text for the overall progress, e.g. `{icon} {percent}% of {count}`
+    #[arg(long, value_name = "TEMPLATE", value_parser = Template::parse_aggregate)]
+    pub aggregate_format: Option<Template>,
+
+    /// Tooltip line for each pending folder
+    #[arg(long, value_name = "TEMPLATE", value_parser = Template::parse)]
+    pub tooltip_format: Option<Template>,
+
+    /// Keep the icon in the text whenever the tooltip has something to show
+    #[arg(long)]
+    pub always_show_icon: bool,
+
+    /// Merge folders at the same percentage into one `N folders at X%` entry
+    #[arg(long)]
+    pub collapse_identical_percent: bool,
+
+    /// Show each folder once in the text, combined across devices
+    #[arg(long)]
+    pub aggregate_across_devices_in_text: bool,
+
+    /// Only show folders that the config shares with the reporting device
+    #[arg(long)]
+    pub folders_from_devices: bool,
+
+    /// Longest time Syncthing may hold a request for events open, in seconds
+    #[arg(long, value_name = "SECS", default_value = "60", value_parser = parse_secs)]
+    pub poll_events_max_wait: Duration,
+
+    /// Ask for the completion of every folder this often instead of following
+    /// events, for setups where `rest/events` does not get through, in seconds
+    #[arg(long, value_name = "SECS", value_parser = parse_secs)]
+    pub poll_interval: Option<Duration>,
+
+    /// Minimum time between two printed statuses, in milliseconds
+    #[arg(long, value_name = "MS", value_parser = parse_millis)]
+    pub output_interval: Option<Duration>,
+
+    /// Forget progress that Syncthing has not updated for this many seconds
+    #[arg(long, value_name = "SECS", value_parser = parse_secs)]
+    pub pending_ttl: Option<Duration>,
+
+    /// How to render byte sizes in the text
+    #[arg(long, value_enum, default_value_t = ByteFormat::Full)]
+    pub text_bytes: ByteFormat,
+
+    /// How to render byte sizes in the tooltip
+    #[arg(long, value_enum, default_value_t = ByteFormat::Full)]
+    pub tooltip_bytes: ByteFormat,
+
+    /// Show each device's transfer rate and the time left in the tooltip
+    #[arg(long)]
+    pub show_rate: bool,
+
+    /// Write the tooltip as plain text, for bars that do not render Pango markup
+    #[arg(long)]
+    pub no_markup: bool,
+
+    /// Show sizes in powers of 1000 (kB, MB, GB) instead of 1024 (KiB, MiB, GiB)
+    #[arg(long)]
+    pub si_units: bool,
+
+    /// Skip polling connections and rely on DeviceDisconnected events alone;
+    /// saves a request per update, but a missed event leaves stale progress
+    #[arg(long)]
+    pub completion_events_only: bool,
+
+    /// Show the file currently being transferred, from `rest/events/disk`
+    #[arg(long)]
+    pub disk_events: bool,
+
+    /// Reload device and folder names as soon as the Syncthing config is saved
+    #[arg(long)]
+    pub refresh_on_config_changed: bool,
+
+    /// Print the Syncthing event types this build understands and exit
+    #[arg(long)]
+    pub list_events: bool,
+
+    /// Keep the event position and progress in $XDG_CACHE_HOME across restarts
+    #[arg(long)]
+    pub persist_state: bool,
+
+    /// Keep device and folder names in $XDG_CACHE_HOME, to start without
+    /// fetching the config
+    #[arg(long)]
+    pub cache_names: bool,
+
+    /// Print the current status once, from the completion of every folder, and exit
+    #[arg(long)]
+    pub once: bool,
+
+    /// Keep tracking progress but only print the status when a line is read on stdin
+    #[arg(long)]
+    pub print_on_signal: bool,
+
+    /// Shape of the printed JSON
+    #[arg(long, value_enum, default_value_t = OutputFormat::Waybar)]
+    pub output_format: OutputFormat,
+
+    /// Include the tracked progress as reported by Syncthing under a `debug` key
+    #[arg(long)]
+    pub raw: bool,
+
+    /// Log to stderr: -v for what is going on, -vv for every request and event,
+    /// -vvv for the HTTP stack as well
+    #[arg(short, long, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Only show this device, by ID or name, can be repeated
+    #[arg(long, value_name = "DEVICE")]
+    pub include_device: Vec<String>,
+
+    /// Hide this device, by ID or name, can be repeated
+    #[arg(long, value_name = "DEVICE")]
+    pub exclude_device: Vec<String>,
+
+    /// Only show this folder, by ID or name, can be repeated
+    #[arg(long, value_name = "FOLDER")]
+    pub include_folder: Vec<String>,
+
+    /// Hide this folder, by ID or name, can be repeated
+    #[arg(long, value_name = "FOLDER")]
+    pub exclude_folder: Vec<String>,
+
+    /// Show this device before all others, can be repeated to pin several in order
+    #[arg(long = "pin-device", value_name = "ID")]
+    pub pin_device: Vec<String>,
+
+    /// List these devices under a NAME header in the tooltip, can be repeated
+    #[arg(long, value_name = "NAME=ID,...", value_parser = parse_device_group)]
+    pub device_group: Vec<(String, Vec<String>)>,
+
+    /// Shell command to run when a remote device finishes syncing a folder
+    #[arg(long, value_name = "CMD")]
+    pub on_complete: Option<String>,
+
+    /// Send a desktop notification when a remote device finishes syncing a folder
+    #[arg(long)]
+    pub notify: bool,
+
+    /// Ignore progress that arrives this soon after a folder completed, in milliseconds
+    #[arg(long, value_name = "MS", default_value = "2000", value_parser = parse_millis)]
+    pub completion_grace: Duration,
+
+    /// Query the completion of every shared folder on startup instead of waiting for events
+    #[arg(long)]
+    pub bootstrap_completion: bool,
+
+    /// Upper bound on the requests sent in parallel while bootstrapping
+    #[arg(
+        long,
+        value_name = "N",
+        default_value = "4",
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub max_concurrent_requests: usize,
+
+    /// Skip bootstrapping folders that were fully synced less than this many seconds ago
+    #[arg(long, value_name = "SECS", value_parser = parse_secs)]
+    pub completion_cache_ttl: Option<Duration>,
+
+    /// Poll the completion of pending folders when no events arrived for this many seconds
+    #[arg(long, value_name = "SECS", value_parser = parse_secs)]
+    pub completion_poll_fallback: Option<Duration>,
+
+    /// How to show devices that have no name set
+    #[arg(long, value_enum, default_value_t = UnnamedDevices::ShortId)]
+    pub unnamed_devices: UnnamedDevices,
+
+    /// Consider folders done once they reach this percentage
+    #[arg(long, value_name = "PERCENT", default_value = "100", value_parser = parse_percent)]
+    pub completion_threshold: f64,
+
+    /// Per-folder override of --completion-threshold, can be repeated
+    #[arg(long, value_name = "ID=PERCENT", value_parser = parse_folder_threshold)]
+    pub folder_threshold: Vec<(String, f64)>,
+
+    /// What counts as done when computing a folder's completion
+    #[arg(long, value_enum, default_value_t = CompletionMode::Sync)]
+    pub completion_mode: CompletionMode,
+
+    /// How percentages are rounded for display
+    #[arg(long, value_enum, default_value_t = PercentRound::Round)]
+    pub percent_round: PercentRound,
+
+    /// Split the syncing class into syncing-low, syncing-mid and syncing-high
+    /// at these overall percentages
+    #[arg(long, value_name = "LOW,HIGH", value_parser = parse_class_thresholds)]
+    pub class_thresholds: Option<(f64, f64)>,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteFormat {
+    /// Unit with up to two decimals, e.g. `2.35 GiB`
+    Full,
+    /// Rounded with a single-letter unit, e.g. `2G`
+    Short,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnnamedDevices {
+    /// First group of the device ID, e.g. `MFZWI3D`
+    ShortId,
+    /// The complete device ID
+    FullId,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// `text`, `tooltip` and friends, as read by waybar's custom module
+    Waybar,
+    /// Also every pending entry and the aggregate, e.g. for eww widgets
+    JsonFull,
+    /// Only the text, one line per status, e.g. for polybar
+    Plain,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionMode {
+    /// The completion as reported by Syncthing, based on bytes
+    Sync,
+    /// Also require every item to be synced, including pending deletes
+    Strict,
 }
 
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstanceLabel {
+    /// A heading above the instance's indented lines
+    Grouped,
+    /// In front of each of the instance's headings, e.g. `work: laptop`
+    Prefix,
+    /// After each of the instance's headings, e.g. `laptop (work)`
+    Suffix,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// How far behind each remote device is, from events
+    Remote,
+    /// One overall percentage for this device, polled on a timer
+    Local,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregateMode {
+    /// Weighted by folder size, so the percentage follows the bytes left
+    Weighted,
+    /// The plain average of the folder percentages
+    Mean,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PercentRound {
+    /// Round down, so 100% only shows once a folder is complete
+    Floor,
+    /// Round to the nearest percent
+    Round,
+    /// Round up
+    Ceil,
+}
+
+/// What stands in for `--icon` with `--no-icons`.
+const NO_ICONS_LABEL: &str = "Syncthing:";
+
 impl Args {
-    pub fn parse_secret(input: &str) -> Result<String> {
-        if Path::new(input).exists() {
+    /// Parses the command line over the config file, filling in whatever was
+    /// not given explicitly from Syncthing's config.xml when one is configured.
+    pub fn load() -> Result<Self> {
+        // The env file has to be loaded before clap resolves `env = ...` values.
+        if let Some(path) = env_file::path_from_args(env::args_os().skip(1)) {
+            env_file::load(Path::new(&path))?;
+        }
+
+        let mut argv = env::args_os().collect::<Vec<_>>();
+        if let Some(path) = config_file::default_path().filter(|path| path.exists()) {
+            let file_args = Args::config_file_args(&path, &argv)?;
+            argv.splice(1..1, file_args);
+        }
+
+        let matches = match Args::command().try_get_matches_from(argv) {
+            // Printed to stdout with a successful exit, unlike real errors.
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    ErrorKind::DisplayHelp | ErrorKind::DisplayVersion
+                ) =>
+            {
+                err.exit()
+            }
+            matches => matches?,
+        };
+        let mut args = Args::from_arg_matches(&matches)?;
+        if args.no_icons {
+            args.icon = NO_ICONS_LABEL.to_string();
+        }
+
+        if args.print_on_signal && args.api_key.iter().any(|key| key == "-") {
+            bail!("--print-on-signal reads stdin, so the API key cannot be read from it");
+        }
+        // Resolved once for all instances, stdin cannot be read a second time.
+        if let Some(command) = &args.api_key_cmd {
+            args.api_key = vec![Args::run_secret_command(command)?];
+        } else if !args.list_events {
+            args.api_key = args
+                .api_key
+                .iter()
+                .map(|key| Args::parse_secret(key))
+                .collect::<Result<_>>()?;
+        }
+
+        if let Some(path) = &args.syncthing_config {
+            let gui = GuiConfig::read(path)?;
+            if args.api_key.is_empty() {
+                args.api_key.extend(gui.api_key.clone());
+            }
+            if matches.value_source("base_url") == Some(ValueSource::DefaultValue) {
+                if let Some(base_url) = gui.base_url() {
+                    args.base_url = vec![base_url];
+                }
+            }
+        }
+
+        Ok(args)
+    }
+
+    /// Whether the text lists folders one by one instead of the overall progress.
+    pub fn per_folder_segments(&self) -> bool {
+        self.per_folder_text
+            || self.max_segments.is_some()
+            || self.cluster_view
+            || self.collapse_identical_percent
+            || self.aggregate_across_devices_in_text
+    }
+
+    /// Every Syncthing instance to show, with the API key for it.
+    pub fn instances(&self) -> Result<Vec<(&str, &str)>> {
+        let keys = match self.api_key.as_slice() {
+            [] => bail!("missing API key"),
+            [key] => vec![key; self.base_url.len()],
+            keys if keys.len() == self.base_url.len() => keys.iter().collect(),
+            _ => bail!("--api-key has to be given once, or once for every --base-url"),
+        };
+
+        Ok(self
+            .base_url
+            .iter()
+            .zip(keys)
+            .map(|(base_url, key)| (base_url.as_str(), key.as_str()))
+            .collect())
+    }
+
+    /// The config file settings as command line options, leaving out those
+    /// already given on the command line or through the environment.
+    fn config_file_args(path: &Path, argv: &[OsString]) -> Result<Vec<OsString>> {
+        let command = Args::command();
+        // Only to find what was given, the full parse reports any errors.
+        let given = Args::command()
+            .ignore_errors(true)
+            .try_get_matches_from(argv)
+            .ok();
+
+        let mut file_args = Vec::new();
+        for (key, value) in config_file::read(path)? {
+            let Some(arg) = command
+                .get_arguments()
+                .find(|arg| arg.get_id() == key.as_str())
+            else {
+                bail!("{}: unknown setting {}", path.display(), key);
+            };
+            let Some(long) = arg.get_long() else {
+                bail!(
+                    "{}: {} cannot be set from the config file",
+                    path.display(),
+                    key
+                );
+            };
+
+            let source = given.as_ref().and_then(|given| given.value_source(&key));
+            if matches!(
+                source,
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            ) {
+                continue;
+            }
+
+            match value {
+                Value::Bool(true) if !arg.get_action().takes_values() => {
+                    file_args.push(format!("--{}", long).into());
+                }
+                Value::Bool(false) if !arg.get_action().takes_values() => {}
+                Value::Bool(value) => file_args.push(format!("--{}={}", long, value).into()),
+                Value::Scalar(value) => file_args.push(format!("--{}={}", long, value).into()),
+                Value::List(values) => file_args.extend(
+                    values
+                        .into_iter()
+                        .map(|value| format!("--{}={}", long, value).into()),
+                ),
+            }
+        }
+
+        Ok(file_args)
+    }
+
+    fn run_secret_command(command: &str) -> Result<String> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output()
+            .with_context(|| format!("Failed to run --api-key-cmd `{}`", command))?;
+        if !output.status.success() {
+            bail!("--api-key-cmd `{}` failed with {}", command, output.status);
+        }
+
+        let secret = String::from_utf8(output.stdout)?;
+        let secret = secret.trim();
+        if secret.is_empty() {
+            bail!("--api-key-cmd `{}` printed no API key", command);
+        }
+        Ok(secret.to_string())
+    }
+
+    fn parse_secret(input: &str) -> Result<String> {
+        if input == "-" {
+            let mut secret = String::new();
+            io::stdin().read_to_string(&mut secret)?;
+            let secret = secret.trim();
+            if secret.is_empty() {
+                bail!("No API key was given on stdin");
+            }
+            Ok(secret.to_string())
+        } else if Path::new(input).exists() {
             Ok(fs::read_to_string(input)?.trim().to_string())
         } else {
             Ok(input.to_string())
         }
     }
 }
+
+fn parse_millis(input: &str) -> Result<Duration> {
+    Ok(Duration::from_millis(input.parse()?))
+}
+
+fn parse_folder_threshold(input: &str) -> Result<(String, f64)> {
+    let Some((folder, percent)) = input.rsplit_once('=') else {
+        bail!("expected <folder id>=<percent>");
+    };
+
+    Ok((folder.to_string(), parse_percent(percent)?))
+}
+
+fn parse_percent(input: &str) -> Result<f64> {
+    let percent = input.trim().parse::<f64>()?;
+    if !(0.0..=100.0).contains(&percent) {
+        bail!("expected a percentage between 0 and 100");
+    }
+
+    Ok(percent)
+}
+
+fn parse_class_thresholds(input: &str) -> Result<(f64, f64)> {
+    let Some((low, high)) = input.split_once(',') else {
+        bail!("expected <low>,<high>");
+    };
+    let (low, high) = (parse_percent(low)?, parse_percent(high)?);
+    if low >= high {
+        bail!("the low threshold must be below the high one");
+    }
+
+    Ok((low, high))
+}
+
+fn parse_device_group(input: &str) -> Result<(String, Vec<String>)> {
+    let Some((name, devices)) = input.split_once('=') else {
+        bail!("expected <name>=<device id>,...");
+    };
+
+    let devices = devices
+        .split(',')
+        .map(str::trim)
+        .filter(|device| !device.is_empty())
+        .map(str::to_string)
+        .collect();
+    Ok((name.to_string(), devices))
+}
+
+fn parse_secs(input: &str) -> Result<Duration> {
+    Ok(Duration::from_secs(input.parse()?))
+}
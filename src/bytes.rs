@@ -0,0 +1,76 @@
+use crate::args::ByteFormat;
+use serde::Deserialize;
+
+/// A byte count as reported by Syncthing, with the formatting every byte
+/// figure in the output shares.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Bytes(pub u64);
+
+const BINARY_UNITS: &[(u64, &str)] = &[
+    (1024 * 1024 * 1024 * 1024, "TiB"),
+    (1024 * 1024 * 1024, "GiB"),
+    (1024 * 1024, "MiB"),
+    (1024, "KiB"),
+    (1, "B"),
+];
+
+const SI_UNITS: &[(u64, &str)] = &[
+    (1000 * 1000 * 1000 * 1000, "TB"),
+    (1000 * 1000 * 1000, "GB"),
+    (1000 * 1000, "MB"),
+    (1000, "kB"),
+    (1, "B"),
+];
+
+impl Bytes {
+    /// Formats in the largest unit the value reaches, so that a nearly complete
+    /// sync shows what is left rather than `0 MiB`. With `si` the units are
+    /// powers of 1000, as parts of the Syncthing GUI show them.
+    pub fn format(&self, format: ByteFormat, si: bool) -> String {
+        let units = if si { SI_UNITS } else { BINARY_UNITS };
+        let (scale, unit) = units
+            .iter()
+            .find(|(scale, _)| self.0 >= *scale)
+            .unwrap_or(&units[units.len() - 1]);
+        let value = self.0 as f64 / *scale as f64;
+
+        match format {
+            ByteFormat::Full if value.fract() == 0.0 => format!("{:.0} {}", value, unit),
+            ByteFormat::Full => format!("{:.2} {}", value, unit),
+            ByteFormat::Short => format!("{:.0}{}", value, &unit[..1]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_the_largest_unit_reached() {
+        assert_eq!(Bytes(0).format(ByteFormat::Full, false), "0 B");
+        assert_eq!(Bytes(1023).format(ByteFormat::Full, false), "1023 B");
+        assert_eq!(Bytes(1024).format(ByteFormat::Full, false), "1 KiB");
+        assert_eq!(
+            Bytes(5 * 1024 * 1024 / 2).format(ByteFormat::Full, false),
+            "2.50 MiB"
+        );
+        assert_eq!(
+            Bytes(3 * 1024 * 1024 * 1024 * 1024).format(ByteFormat::Full, false),
+            "3 TiB"
+        );
+    }
+
+    #[test]
+    fn si_units_are_powers_of_1000() {
+        assert_eq!(Bytes(1000).format(ByteFormat::Full, true), "1 kB");
+        assert_eq!(Bytes(1024).format(ByteFormat::Full, true), "1.02 kB");
+        assert_eq!(Bytes(2_500_000_000).format(ByteFormat::Short, true), "2G");
+    }
+
+    #[test]
+    fn short_format_rounds_to_one_letter() {
+        assert_eq!(Bytes(512).format(ByteFormat::Short, false), "512B");
+        assert_eq!(Bytes(2_950_000_000).format(ByteFormat::Short, false), "3G");
+    }
+}
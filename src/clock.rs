@@ -0,0 +1,32 @@
+use std::{
+    fmt, thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Where `Runners` and every `Runner` get the time from, so that tests can
+/// move it forward instead of sleeping.
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// The wall-clock time, for what is shown as a time of day or an age.
+    fn now_system(&self) -> SystemTime;
+
+    fn sleep(&self, duration: Duration);
+}
+
+#[derive(Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn now_system(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
@@ -0,0 +1,125 @@
+use anyhow::{bail, Context, Result};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+/// A value in the config file, as far as the command line options need.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Value {
+    Bool(bool),
+    List(Vec<String>),
+    Scalar(String),
+}
+
+/// `$XDG_CONFIG_HOME/waybar-syncthing/config.toml`, or under `~/.config`
+/// when that is not set.
+pub fn default_path() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(base.join("waybar-syncthing").join("config.toml"))
+}
+
+/// Reads the `key = value` settings of the config file, with keys named like
+/// the command line options. Only flat TOML is accepted: strings, numbers,
+/// booleans and single-line arrays of strings.
+pub fn read(path: &Path) -> Result<Vec<(String, Value)>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file {}", path.display()))?;
+
+    let mut settings = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            bail!("{}:{}: expected key = value", path.display(), number + 1);
+        };
+        let value = parse_value(value.trim())
+            .with_context(|| format!("{}:{}: invalid value", path.display(), number + 1))?;
+        settings.push((key.trim().replace('-', "_"), value));
+    }
+
+    Ok(settings)
+}
+
+fn parse_value(value: &str) -> Result<Value> {
+    if let Some(items) = value.strip_prefix('[') {
+        let Some(items) = items.strip_suffix(']') else {
+            bail!("arrays have to be on a single line");
+        };
+        return items
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(unquote)
+            .collect::<Result<_>>()
+            .map(Value::List);
+    }
+
+    if value.starts_with(['"', '\'']) {
+        return unquote(value).map(Value::Scalar);
+    }
+
+    // Anything unquoted may be followed by a comment.
+    let value = value.split('#').next().unwrap_or_default().trim();
+    Ok(match value {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        _ => Value::Scalar(value.to_string()),
+    })
+}
+
+fn unquote(value: &str) -> Result<String> {
+    ['"', '\'']
+        .iter()
+        .find_map(|quote| {
+            let rest = value.strip_prefix(*quote)?;
+            let end = rest.find(*quote)?;
+            Some(rest[..end].to_string())
+        })
+        .with_context(|| format!("expected a quoted string, got {}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_are_parsed_by_their_shape() {
+        assert_eq!(parse_value("true").unwrap(), Value::Bool(true));
+        assert_eq!(parse_value("false").unwrap(), Value::Bool(false));
+        assert_eq!(parse_value("500").unwrap(), Value::Scalar("500".into()));
+        assert_eq!(
+            parse_value("'single'").unwrap(),
+            Value::Scalar("single".into())
+        );
+        assert_eq!(
+            parse_value(r#"["laptop", 'phone', ]"#).unwrap(),
+            Value::List(vec!["laptop".into(), "phone".into()])
+        );
+    }
+
+    #[test]
+    fn comments_only_end_unquoted_values() {
+        assert_eq!(
+            parse_value("500 # ms").unwrap(),
+            Value::Scalar("500".into())
+        );
+        assert_eq!(
+            parse_value(r#""a # b" # comment"#).unwrap(),
+            Value::Scalar("a # b".into())
+        );
+    }
+
+    #[test]
+    fn malformed_values_are_rejected() {
+        assert!(parse_value(r#"["laptop""#).is_err());
+        assert!(parse_value("[laptop]").is_err());
+        assert!(parse_value(r#""unterminated"#).is_err());
+    }
+}
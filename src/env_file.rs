@@ -0,0 +1,97 @@
+use anyhow::{bail, Context, Result};
+use std::{env, ffi::OsString, fs, path::Path};
+
+/// Exports the `KEY=VALUE` lines of a dotenv file so clap picks them up like
+/// any other environment variable. Variables already set take precedence.
+pub fn load(path: &Path) -> Result<()> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read env file {}", path.display()))?;
+
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, value)) = line.split_once('=') else {
+            bail!("{}:{}: expected KEY=VALUE", path.display(), number + 1);
+        };
+        let key = key.trim();
+        if env::var_os(key).is_none() {
+            env::set_var(key, unquote(value.trim()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Finds `--env-file` on the command line before clap has parsed anything.
+pub fn path_from_args(args: impl IntoIterator<Item = OsString>) -> Option<OsString> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--env-file" {
+            return args.next();
+        }
+        if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--env-file=")) {
+            return Some(path.into());
+        }
+    }
+    None
+}
+
+fn unquote(value: &str) -> &str {
+    ['"', '\'']
+        .iter()
+        .find_map(|quote| {
+            value
+                .strip_prefix(*quote)
+                .and_then(|value| value.strip_suffix(*quote))
+        })
+        .unwrap_or(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_are_stripped_only_in_pairs() {
+        assert_eq!(unquote(r#""key""#), "key");
+        assert_eq!(unquote("'key'"), "key");
+        assert_eq!(unquote(r#""key'"#), r#""key'"#);
+        assert_eq!(unquote("key"), "key");
+    }
+
+    #[test]
+    fn path_is_found_in_either_form() {
+        let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+        assert_eq!(
+            path_from_args(args(&["--once", "--env-file", "a.env"])),
+            Some("a.env".into())
+        );
+        assert_eq!(
+            path_from_args(args(&["--env-file=b.env"])),
+            Some("b.env".into())
+        );
+        assert_eq!(path_from_args(args(&["--env-file"])), None);
+        assert_eq!(path_from_args(args(&["--once"])), None);
+    }
+
+    #[test]
+    fn variables_already_set_take_precedence() {
+        let path = env::temp_dir().join(format!("waybar-syncthing-{}.env", std::process::id()));
+        fs::write(
+            &path,
+            "# comment\n\nexport ENV_FILE_TEST_NEW = 'new'\nENV_FILE_TEST_SET=file\n",
+        )
+        .unwrap();
+        env::set_var("ENV_FILE_TEST_SET", "environment");
+
+        let result = load(&path);
+        fs::remove_file(&path).unwrap();
+        result.unwrap();
+        assert_eq!(env::var("ENV_FILE_TEST_NEW").unwrap(), "new");
+        assert_eq!(env::var("ENV_FILE_TEST_SET").unwrap(), "environment");
+    }
+}
@@ -0,0 +1,41 @@
+use log::{LevelFilter, Log, Metadata, Record};
+use std::io::{self, Write};
+
+/// Writes log messages to stderr, which waybar passes on to its own log.
+struct StderrLogger {
+    /// Whether to include the messages of the HTTP stack and other libraries.
+    dependencies: bool,
+}
+
+static OWN: StderrLogger = StderrLogger {
+    dependencies: false,
+};
+static ALL: StderrLogger = StderrLogger { dependencies: true };
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+            && (self.dependencies || metadata.target().starts_with(env!("CARGO_CRATE_NAME")))
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let _ = writeln!(io::stderr().lock(), "{} {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Warnings only by default, every `-v` adds a level.
+pub fn init(verbose: u8) {
+    let (logger, level) = match verbose {
+        0 => (&OWN, LevelFilter::Warn),
+        1 => (&OWN, LevelFilter::Info),
+        2 => (&OWN, LevelFilter::Debug),
+        _ => (&ALL, LevelFilter::Trace),
+    };
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(level);
+    }
+}
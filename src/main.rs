@@ -1,16 +1,44 @@
 use anyhow::Result;
-use api_client::ApiClient;
+use api_client::{Unauthorized, UNAUTHORIZED_EXIT_CODE};
 use args::Args;
-use clap::Parser;
-use runner::Runner;
+use runner::{Hung, Runner, Runners, HANG_EXIT_CODE};
+use std::process;
 
 mod api_client;
 mod args;
+mod bytes;
+mod clock;
+mod config_file;
+mod env_file;
+mod logger;
+mod notifier;
 mod runner;
+mod shutdown;
+mod syncthing_config;
+mod template;
+mod unix_socket;
 
 fn main() -> Result<()> {
-    let args = Args::try_parse()?;
-    let client = ApiClient::new(&args)?;
+    let args = Args::load()?;
+    logger::init(args.verbose);
+    if args.list_events {
+        Runner::print_event_types(&args);
+        return Ok(());
+    }
 
-    Runner::new(client).main_loop()
+    let mut runners = Runners::new(args)?;
+    shutdown::install(runners.final_status());
+
+    let result = runners.main_loop();
+    if let Err(err) = &result {
+        if err.is::<Hung>() {
+            eprintln!("Error: {:?}", err);
+            process::exit(HANG_EXIT_CODE);
+        }
+        if err.is::<Unauthorized>() {
+            eprintln!("Error: {:?}", err);
+            process::exit(UNAUTHORIZED_EXIT_CODE);
+        }
+    }
+    result
 }
@@ -0,0 +1,67 @@
+use std::{
+    collections::HashMap,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A folder that keeps flipping between 99% and 100% is only announced this
+/// often.
+const DEBOUNCE: Duration = Duration::from_secs(60);
+
+/// Desktop notifications through `notify-send`, for `--notify`.
+#[derive(Debug, Default)]
+pub struct Notifier {
+    last_sent: HashMap<String, Instant>,
+}
+
+impl Notifier {
+    /// Sends the notification unless one with the same key went out less than
+    /// a minute before `now`.
+    pub fn notify(&mut self, now: Instant, key: String, summary: &str, body: &str) {
+        if !self.is_due(now, key) {
+            return;
+        }
+
+        let child = Command::new("notify-send")
+            .args(["--app-name", "waybar-syncthing", summary, body])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn();
+        match child {
+            // Reap it in the background so a slow notification daemon cannot
+            // hold up the bar.
+            Ok(mut child) => {
+                thread::spawn(move || child.wait());
+            }
+            Err(err) => log::warn!("Failed to run notify-send: {}", err),
+        }
+    }
+
+    fn is_due(&mut self, now: Instant, key: String) -> bool {
+        self.last_sent
+            .retain(|_, sent_at| now.duration_since(*sent_at) < DEBOUNCE);
+        if self.last_sent.contains_key(&key) {
+            log::debug!("Not notifying about {} again so soon", key);
+            return false;
+        }
+        self.last_sent.insert(key, now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_key_is_debounced_for_a_minute() {
+        let mut notifier = Notifier::default();
+        let start = Instant::now();
+
+        assert!(notifier.is_due(start, "photos".to_string()));
+        assert!(!notifier.is_due(start + Duration::from_secs(59), "photos".to_string()));
+        assert!(notifier.is_due(start + Duration::from_secs(59), "music".to_string()));
+        assert!(notifier.is_due(start + DEBOUNCE, "photos".to_string()));
+    }
+}
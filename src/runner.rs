@@ -1,205 +1,3219 @@
-use crate::api_client::ApiClient;
-use anyhow::Result;
-use serde::Deserialize;
-use std::{collections::HashMap, fmt};
+use crate::{
+    api_client::{ApiClient, SyncthingApi},
+    args::{
+        AggregateMode, Args, ByteFormat, CompletionMode, InstanceLabel, Mode, OutputFormat,
+        PercentRound, UnnamedDevices,
+    },
+    bytes::Bytes,
+    clock::{Clock, SystemClock},
+    notifier::Notifier,
+    template::Field,
+    unix_socket,
+};
+use anyhow::{Context, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    env,
+    fmt::{self, Write as _},
+    fs,
+    io::{self, BufRead, Write as _},
+    mem,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+use url::form_urlencoded::byte_serialize;
+
+#[derive(Debug)]
+pub struct Runner {
+    client: Box<dyn SyncthingApi>,
+    clock: Arc<dyn Clock>,
+    args: Args,
+    devices: HashMap<DeviceID, DeviceName>,
+    folders: HashMap<FolderID, FolderName>,
+    folder_devices: HashMap<FolderID, Vec<DeviceID>>,
+    pending: HashMap<DeviceID, HashMap<FolderID, FolderProgress>>,
+    folder_sizes: HashMap<(DeviceID, FolderID), FolderProgress>,
+    folder_states: HashMap<FolderID, String>,
+    /// Progress of this device itself, which `pending` has no entry for.
+    local_folders: HashMap<FolderID, LocalFolder>,
+    /// Latest pull errors of every folder, until it completes.
+    folder_errors: HashMap<FolderID, Vec<FolderError>>,
+    paused_devices: HashSet<DeviceID>,
+    /// Devices and folders offered to us that are not in the config yet.
+    pending_approvals: Vec<Approval>,
+    /// Whether each device is connected, from the last read of connections.
+    connected: HashMap<DeviceID, bool>,
+    recently_completed: HashMap<(DeviceID, FolderID), Instant>,
+    quiet_since: HashMap<(DeviceID, FolderID), Instant>,
+    my_id: Option<DeviceID>,
+    needs_bootstrap: bool,
+    needs_folder_states: bool,
+    connections_unavailable: bool,
+    last_event_at: Instant,
+    last_success: Instant,
+    since: u64,
+    disk_since: u64,
+    disk_activity: Option<DiskActivity>,
+    start_time: Option<String>,
+    check_restart: bool,
+    failure: Option<&'static str>,
+    retry_delay: Duration,
+    /// Set while backing off after a failed cycle.
+    retry_at: Option<Instant>,
+    transfer_rates: HashMap<DeviceID, TransferRate>,
+    /// When a pending folder last got in sync, for `--idle-format`.
+    last_synced: Option<SystemTime>,
+    /// This device's completion against the cluster, with `--mode local`.
+    local_completion: Option<FolderProgress>,
+    notifier: Notifier,
+    /// Index into `--device-group` for every grouped device.
+    device_groups: HashMap<String, usize>,
+    /// Keeps the cache files of instances apart, whatever their order in
+    /// `--base-url`.
+    cache_key: String,
+    /// The address of the instance, heading its part of the tooltip when
+    /// there are several.
+    label: String,
+}
+
+/// Every Syncthing instance given with `--base-url`, polled in turn and
+/// shown as one module.
+#[derive(Debug)]
+pub struct Runners {
+    args: Args,
+    clock: Arc<dyn Clock>,
+    runners: Vec<Runner>,
+    last_print: Option<Instant>,
+    print_deferred: bool,
+    text_buffer: String,
+    tooltip_buffer: String,
+    instance_text_buffer: String,
+    instance_tooltip_buffer: String,
+    /// `None` until the first status with `--print-on-signal`.
+    latest_status: Arc<Mutex<Option<String>>>,
+    /// What went to stdout last, to not make waybar redraw the same status.
+    last_line: RefCell<Option<String>>,
+}
+
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Longest long-poll with several instances, so that one with nothing going
+/// on does not hold up the others.
+const SHARED_POLL_WAIT: Duration = Duration::from_secs(2);
+
+/// How often `--mode local` asks for the completion, which no event announces,
+/// unless `--poll-interval` is given.
+const LOCAL_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Exit code used when `--hang-timeout` gives up, so supervisors can tell it
+/// apart from configuration errors.
+pub const HANG_EXIT_CODE: i32 = 3;
+
+/// Nothing reached Syncthing successfully for `--hang-timeout`.
+#[derive(Debug)]
+pub struct Hung(Duration);
+
+impl fmt::Display for Hung {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "No request to Syncthing succeeded for {}s, giving up",
+            self.0.as_secs()
+        )
+    }
+}
+
+/// Every Syncthing event type this build models, and the options that make
+/// it subscribe to them.
+const EVENT_TYPES: &[EventType] = &[
+    EventType {
+        name: "FolderCompletion",
+        stream: EventStream::Main,
+        description: "sync progress of a folder on a remote device",
+        enabled: |_| true,
+    },
+    EventType {
+        name: "DeviceDisconnected",
+        stream: EventStream::Main,
+        description: "drops the progress of a device that went away",
+        enabled: |_| true,
+    },
+    EventType {
+        name: "DeviceConnected",
+        stream: EventStream::Main,
+        description: "asks for the progress of a device that came back",
+        enabled: |_| true,
+    },
+    EventType {
+        name: "FolderPaused",
+        stream: EventStream::Main,
+        description: "drops the progress of a folder that was paused",
+        enabled: |_| true,
+    },
+    EventType {
+        name: "FolderErrors",
+        stream: EventStream::Main,
+        description: "files of a folder that failed to sync",
+        enabled: |_| true,
+    },
+    EventType {
+        name: "FolderSummary",
+        stream: EventStream::Main,
+        description: "scan and sync progress of a local folder (--local-progress)",
+        enabled: |args| args.local_progress,
+    },
+    EventType {
+        name: "ConfigSaved",
+        stream: EventStream::Main,
+        description: "reloads device and folder names (--refresh-on-config-changed)",
+        enabled: |args| args.refresh_on_config_changed || args.show_pending_approvals,
+    },
+    EventType {
+        name: "StateChanged",
+        stream: EventStream::Main,
+        description:
+            "whether a local folder is scanning or syncing (--bucketed-text, --show-scanning)",
+        enabled: |args| args.bucketed_text || args.show_scanning,
+    },
+    EventType {
+        name: "DevicePaused",
+        stream: EventStream::Main,
+        description: "a device was paused (--show-when-paused-globally)",
+        enabled: |args| args.show_when_paused_globally,
+    },
+    EventType {
+        name: "DeviceResumed",
+        stream: EventStream::Main,
+        description: "a device was resumed (--show-when-paused-globally)",
+        enabled: |args| args.show_when_paused_globally,
+    },
+    EventType {
+        name: "DeviceRejected",
+        stream: EventStream::Main,
+        description: "an unknown device tried to connect (--show-pending-approvals)",
+        enabled: |args| args.show_pending_approvals,
+    },
+    EventType {
+        name: "FolderRejected",
+        stream: EventStream::Main,
+        description: "a device shared an unknown folder (--show-pending-approvals)",
+        enabled: |args| args.show_pending_approvals,
+    },
+    EventType {
+        name: "PendingDevicesChanged",
+        stream: EventStream::Main,
+        description: "devices waiting to be accepted (--show-pending-approvals)",
+        enabled: |args| args.show_pending_approvals,
+    },
+    EventType {
+        name: "LocalChangeDetected",
+        stream: EventStream::Disk,
+        description: "file being sent to other devices (--disk-events)",
+        enabled: |args| args.disk_events,
+    },
+    EventType {
+        name: "RemoteChangeDetected",
+        stream: EventStream::Disk,
+        description: "file being received from another device (--disk-events)",
+        enabled: |args| args.disk_events,
+    },
+];
+
+struct EventType {
+    name: &'static str,
+    stream: EventStream,
+    description: &'static str,
+    enabled: fn(&Args) -> bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum EventStream {
+    Main,
+    Disk,
+}
+
+impl Runner {
+    fn new(
+        client: Box<dyn SyncthingApi>,
+        clock: Arc<dyn Clock>,
+        args: Args,
+        label: String,
+    ) -> Self {
+        let device_groups = args
+            .device_group
+            .iter()
+            .enumerate()
+            .flat_map(|(group, (_, devices))| {
+                devices.iter().map(move |device| (device.clone(), group))
+            })
+            .collect();
+        let cache_key = format!("{:016x}", fnv1a(client.base_url().as_bytes()));
+        let now = clock.now();
+
+        let mut runner = Self {
+            client,
+            clock,
+            args,
+            devices: HashMap::new(),
+            folders: HashMap::new(),
+            folder_devices: HashMap::new(),
+            pending: HashMap::new(),
+            folder_sizes: HashMap::new(),
+            folder_states: HashMap::new(),
+            local_folders: HashMap::new(),
+            folder_errors: HashMap::new(),
+            paused_devices: HashSet::new(),
+            pending_approvals: Vec::new(),
+            connected: HashMap::new(),
+            recently_completed: HashMap::new(),
+            quiet_since: HashMap::new(),
+            my_id: None,
+            needs_bootstrap: true,
+            needs_folder_states: true,
+            connections_unavailable: false,
+            last_event_at: now,
+            last_success: now,
+            since: 0,
+            disk_since: 0,
+            disk_activity: None,
+            start_time: None,
+            check_restart: true,
+            failure: None,
+            retry_delay: RETRY_DELAY,
+            retry_at: None,
+            transfer_rates: HashMap::new(),
+            last_synced: None,
+            local_completion: None,
+            notifier: Notifier::default(),
+            device_groups,
+            cache_key,
+            label,
+        };
+        if runner.args.persist_state {
+            runner.load_state();
+        }
+        if runner.args.cache_names {
+            runner.load_names();
+        }
+        runner
+    }
+
+    /// Waits twice as long after every failed cycle in a row, so that a
+    /// Syncthing which is down for a while is not polled every second.
+    fn back_off(&mut self) {
+        self.retry_at = Some(self.clock.now() + self.retry_delay);
+        self.retry_delay = (self.retry_delay * 2).min(MAX_RETRY_DELAY);
+    }
+
+    /// Nothing succeeded for longer than a long-poll and a request timeout.
+    fn is_stale(&self) -> bool {
+        self.clock.now().duration_since(self.last_success)
+            >= self.args.poll_events_max_wait + self.args.timeout_secs
+    }
+
+    fn succeeded(&mut self) {
+        let now = self.clock.now();
+        self.last_success = now;
+        self.retry_delay = RETRY_DELAY;
+        self.retry_at = self.poll_interval().map(|interval| now + interval);
+    }
+
+    /// How long to wait between cycles that poll rather than follow events.
+    fn poll_interval(&self) -> Option<Duration> {
+        self.args
+            .poll_interval
+            .or((self.args.mode == Mode::Local).then_some(LOCAL_POLL_INTERVAL))
+    }
+
+    fn note_reachable(&mut self) {
+        if self.failure.take().is_some() {
+            log::info!("Syncthing at {} is reachable again", self.label);
+        }
+    }
+
+    /// Events only describe changes since `since`, so a single status has to
+    /// come from asking for every folder's completion instead.
+    fn run_once(&mut self) -> Result<()> {
+        self.detect_restart()?;
+        if self.args.mode == Mode::Local {
+            return self.poll_local_completion();
+        }
+        self.bootstrap_completion()?;
+        if !self.args.completion_events_only {
+            self.refresh_connected_devices()?;
+        }
+
+        Ok(())
+    }
+
+    fn run_cycle(&mut self, max_wait: Duration) -> Result<()> {
+        if self.check_restart {
+            self.detect_restart()?;
+            self.check_restart = false;
+        }
+        if self.args.mode == Mode::Local {
+            self.poll_local_completion()?;
+            self.note_reachable();
+            return Ok(());
+        }
+        if self.args.poll_interval.is_some() {
+            self.poll_all_completion()?;
+            self.note_reachable();
+            return Ok(());
+        }
+        if self.args.bootstrap_completion && self.needs_bootstrap {
+            self.bootstrap_completion()?;
+            self.needs_bootstrap = false;
+        }
+        if self.args.bucketed_text && self.needs_folder_states {
+            self.seed_folder_states()?;
+            self.needs_folder_states = false;
+        }
+
+        // Pausing everything produces no further events, so the paused state
+        // has to be known from the config up front.
+        if self.args.show_when_paused_globally && self.devices.is_empty() {
+            self.refresh_devices_and_folders()?;
+        }
+
+        self.get_events(max_wait)?;
+        self.note_reachable();
+        if self.args.disk_events {
+            self.get_disk_events()?;
+        }
+        self.poll_stale_completion()?;
+        self.expire_stale_pending();
+        self.forget_finished_disk_activity();
+        if self.args.persist_state {
+            self.save_state();
+        }
+
+        Ok(())
+    }
+
+    /// Picks up where the previous run left off. A Syncthing restart since
+    /// then is caught by the start time check like any other.
+    fn load_state(&mut self) {
+        let Some(state) = read_cache_json::<PersistedState>("state", &self.cache_key) else {
+            return;
+        };
+
+        self.since = state.since;
+        self.start_time = state.start_time;
+        self.last_synced = state
+            .last_synced
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+        let now = self.clock.now();
+        self.pending = state
+            .pending
+            .into_iter()
+            .map(|(device, folders)| {
+                let folders = folders
+                    .into_iter()
+                    .map(|(folder, progress)| (folder, progress.restore(now)))
+                    .collect();
+                (device, folders)
+            })
+            .collect();
+        self.needs_bootstrap = self.pending.is_empty();
+    }
+
+    fn save_state(&self) {
+        let state = PersistedState {
+            since: self.since,
+            start_time: self.start_time.clone(),
+            last_synced: self
+                .last_synced
+                .and_then(|at| at.duration_since(UNIX_EPOCH).ok())
+                .map(|elapsed| elapsed.as_secs()),
+            pending: self
+                .pending
+                .iter()
+                .map(|(device, folders)| {
+                    let folders = folders
+                        .iter()
+                        .map(|(folder, progress)| (folder.clone(), progress.into()))
+                        .collect();
+                    (device.clone(), folders)
+                })
+                .collect(),
+        };
+        write_cache_json("state", &self.cache_key, &state);
+    }
+
+    /// Names from the previous run, so that the first events do not each need
+    /// the config. An ID missing from them still triggers a refresh.
+    fn load_names(&mut self) {
+        let Some(names) = read_cache_json::<CachedNames>("names", &self.cache_key) else {
+            return;
+        };
+
+        self.devices = names.devices;
+        self.folders = names.folders;
+        self.folder_devices = names.folder_devices;
+        self.paused_devices = names.paused_devices;
+    }
+
+    fn save_names(&self) {
+        let names = CachedNames {
+            devices: self.devices.clone(),
+            folders: self.folders.clone(),
+            folder_devices: self.folder_devices.clone(),
+            paused_devices: self.paused_devices.clone(),
+        };
+        write_cache_json("names", &self.cache_key, &names);
+    }
+
+    /// Only a restarted Syncthing invalidates `since`, so after a failed request
+    /// compare its start time rather than assuming the event sequence is gone.
+    fn detect_restart(&mut self) -> Result<()> {
+        let response = match self
+            .client
+            .get("rest/system/status")
+            .and_then(|response| response.json::<SystemStatusResponse>())
+        {
+            Ok(response) => response,
+            // A key restricted to some endpoints or a proxy in front of
+            // Syncthing leaves a restart unknown, which is no reason to stop.
+            Err(err) if is_client_error(&err) => {
+                log::warn!("Cannot tell whether Syncthing restarted: {:#}", err);
+                return Ok(());
+            }
+            Err(err) => return Err(err.context("Failed to read rest/system/status")),
+        };
+
+        if self
+            .start_time
+            .as_ref()
+            .is_some_and(|start_time| *start_time != response.start_time)
+        {
+            log::info!("Syncthing restarted, resetting event position");
+            self.reset_event_position();
+        }
+        self.start_time = Some(response.start_time);
+        self.my_id = response.my_id;
+
+        Ok(())
+    }
+
+    /// Progress tracked so far belongs to events that no longer exist.
+    fn reset_event_position(&mut self) {
+        self.since = 0;
+        self.disk_since = 0;
+        self.pending.clear();
+        self.needs_bootstrap = true;
+        self.needs_folder_states = true;
+    }
+
+    fn get_events(&mut self, max_wait: Duration) -> Result<()> {
+        let mut path = format!(
+            "rest/events?since={}&events={}",
+            self.since,
+            self.subscribed_events().join(",")
+        );
+        // Return from the long-poll in time to print a deferred status or to
+        // fall back to polling completion.
+        let wait = self
+            .fallback_wait(self.clock.now())
+            .map_or(max_wait, |wait| wait.min(max_wait));
+        // Syncthing only takes whole seconds.
+        let wait = Duration::from_secs_f64(wait.as_secs_f64().ceil().max(1.));
+        path.push_str(&format!("&timeout={}", wait.as_secs()));
+
+        let mut response = self
+            .client
+            .long_poll(&path, wait)
+            .and_then(|response| response.json::<EventsResponse>())
+            .with_context(|| format!("Failed to read rest/events since {}", self.since))?;
+        log::debug!(
+            "Received events {:?}",
+            response.iter().map(|entry| entry.id).collect::<Vec<_>>()
+        );
+        // Syncthing only returns events after `since`, so an older ID means the
+        // sequence started over without the restart being noticed.
+        if response.first().is_some_and(|entry| entry.id <= self.since) {
+            log::info!("Event IDs went back, resetting event position");
+            self.reset_event_position();
+        }
+        response.iter_mut().for_each(|entry| {
+            if let EventsResponseData::FolderCompletion {
+                completion, items, ..
+            } = &mut entry.data
+            {
+                *completion = items.completion(*completion, self.args.completion_mode);
+            }
+        });
+
+        let need_device_refresh = response
+            .iter()
+            .filter_map(|entry| match &entry.data {
+                EventsResponseData::FolderCompletion { device, .. }
+                | EventsResponseData::DeviceConnected { id: device }
+                | EventsResponseData::FolderRejected { device, .. } => Some(device),
+                _ => None,
+            })
+            .any(|item| !self.devices.contains_key(item));
+
+        let need_folder_refresh = response
+            .iter()
+            .filter_map(|entry| match &entry.data {
+                EventsResponseData::FolderCompletion { folder, .. }
+                | EventsResponseData::FolderSummary { folder, .. } => Some(folder),
+                _ => None,
+            })
+            .any(|item| !self.folders.contains_key(item));
+
+        let config_saved = response
+            .iter()
+            .any(|entry| matches!(entry.data, EventsResponseData::ConfigSaved {}));
+
+        // Left behind, a failed refresh would bring the old names back on the
+        // next start.
+        if config_saved && self.args.cache_names {
+            if let Some(path) = cache_path("names", &self.cache_key) {
+                let _ = fs::remove_file(path);
+            }
+        }
+        if need_device_refresh || need_folder_refresh || config_saved {
+            self.refresh_devices_and_folders()?;
+        }
+
+        let now = self.clock.now();
+        response.iter().for_each(|entry| {
+            if let EventsResponseData::FolderCompletion {
+                device,
+                folder,
+                completion,
+                need_bytes,
+                global_bytes,
+                items,
+            } = &entry.data
+            {
+                self.folder_sizes.insert(
+                    (device.clone(), folder.clone()),
+                    FolderProgress::new(*completion, *need_bytes, *global_bytes, *items, now),
+                );
+                self.record_quiet(device, folder, *completion, now);
+            }
+        });
+
+        let grace = self.args.completion_grace;
+        self.recently_completed
+            .retain(|_, completed_at| now.duration_since(*completed_at) < grace);
+
+        response.iter().for_each(|entry| match &entry.data {
+            EventsResponseData::FolderCompletion {
+                device,
+                folder,
+                completion,
+                ..
+            } if self.is_complete(folder, *completion) => {
+                let was_pending = self
+                    .pending
+                    .get_mut(device)
+                    .and_then(|folders| folders.remove(folder))
+                    .is_some();
+                self.folder_errors.remove(folder);
+                let key = (device.clone(), folder.clone());
+                if was_pending {
+                    log::debug!("{} completed on {}", folder.as_str(), device.as_str());
+                    self.last_synced = Some(self.clock.now_system());
+                }
+                if was_pending && !self.recently_completed.contains_key(&key) {
+                    self.run_on_complete(device, folder);
+                    if self.args.notify {
+                        let device_name = self.device_name(device).to_string();
+                        let folder_name = self.folder_name(folder).to_string();
+                        self.notifier.notify(
+                            now,
+                            format!("{} on {}", folder.as_str(), device.as_str()),
+                            &format!("{} is in sync", folder_name),
+                            &format!("{} finished syncing {}", device_name, folder_name),
+                        );
+                    }
+                }
+                self.recently_completed.insert(key, now);
+            }
+            // A stale event arriving right after completion would bring the
+            // folder back for a moment, wait for the next one instead.
+            EventsResponseData::FolderCompletion { device, folder, .. }
+                if self
+                    .recently_completed
+                    .contains_key(&(device.clone(), folder.clone())) =>
+            {
+                log::debug!(
+                    "Ignoring progress of {} on {} right after completion",
+                    folder.as_str(),
+                    device.as_str()
+                );
+            }
+            EventsResponseData::FolderCompletion {
+                device,
+                folder,
+                completion,
+                need_bytes,
+                global_bytes,
+                items,
+            } => {
+                log::debug!(
+                    "{} on {} is at {}%",
+                    folder.as_str(),
+                    device.as_str(),
+                    completion.0
+                );
+                self.pending.entry(device.clone()).or_default().insert(
+                    folder.clone(),
+                    FolderProgress::new(*completion, *need_bytes, *global_bytes, *items, now),
+                );
+            }
+
+            EventsResponseData::DeviceConnected { id } => {
+                log::debug!("{} connected", id.as_str());
+                self.connected.insert(id.clone(), true);
+            }
+
+            EventsResponseData::DeviceDisconnected { id } => {
+                log::debug!("{} disconnected", id.as_str());
+                self.connected.insert(id.clone(), false);
+                if !self.args.keep_disconnected {
+                    self.pending.remove(id);
+                }
+            }
+
+            // No completion event follows a pause, so the progress would stay
+            // stuck; resuming brings new completion events.
+            EventsResponseData::FolderPaused { id } => {
+                log::debug!("{} paused", id.as_str());
+                self.pending.values_mut().for_each(|folders| {
+                    folders.remove(id);
+                });
+                self.folder_sizes.retain(|(_, folder), _| folder != id);
+                self.folder_states.remove(id);
+                self.local_folders.remove(id);
+                self.folder_errors.remove(id);
+            }
+
+            EventsResponseData::FolderErrors { folder, errors } if errors.is_empty() => {
+                self.folder_errors.remove(folder);
+            }
+            EventsResponseData::FolderErrors { folder, errors } => {
+                self.folder_errors.insert(folder.clone(), errors.clone());
+            }
+
+            EventsResponseData::FolderSummary { folder, summary } => {
+                let completion = if summary.global_bytes.0 > 0 {
+                    let done = summary.global_bytes.0.saturating_sub(summary.need_bytes.0);
+                    100. * done as f64 / summary.global_bytes.0 as f64
+                } else {
+                    100.
+                };
+                self.local_folders.insert(
+                    folder.clone(),
+                    LocalFolder {
+                        progress: FolderProgress::new(
+                            ProgressPct(completion),
+                            Some(summary.need_bytes),
+                            summary.global_bytes,
+                            ItemCounts {
+                                need_items: summary.need_items,
+                                ..ItemCounts::default()
+                            },
+                            now,
+                        ),
+                        state: summary.state.clone(),
+                    },
+                );
+            }
+
+            // Going idle ends a scan or pull even when no completion event
+            // reached 100%.
+            EventsResponseData::StateChanged { folder, to } if to == "idle" => {
+                log::debug!("{} is idle", folder.as_str());
+                self.folder_states.remove(folder);
+                if let Some(local) = self.local_folders.get_mut(folder) {
+                    local.state = to.clone();
+                }
+            }
+            EventsResponseData::StateChanged { folder, to } => {
+                log::debug!("{} is {}", folder.as_str(), to);
+                self.folder_states.insert(folder.clone(), to.clone());
+                if let Some(local) = self.local_folders.get_mut(folder) {
+                    local.state = to.clone();
+                }
+            }
+
+            EventsResponseData::DevicePaused { device } => {
+                self.paused_devices.insert(device.clone());
+            }
+
+            EventsResponseData::DeviceResumed { device } => {
+                self.paused_devices.remove(device);
+            }
+
+            EventsResponseData::DeviceRejected { device, name } => {
+                log::debug!("{} wants to connect", device.as_str());
+                self.add_approval(Approval::Device {
+                    device: device.clone(),
+                    name: name.clone(),
+                });
+            }
+            EventsResponseData::FolderRejected {
+                device,
+                folder,
+                folder_label,
+            } => {
+                log::debug!("{} shares {}", device.as_str(), folder.as_str());
+                self.add_approval(Approval::Folder {
+                    device: device.clone(),
+                    folder: folder.clone(),
+                    label: folder_label.clone(),
+                });
+            }
+            EventsResponseData::PendingDevicesChanged { added, removed } => {
+                for pending in added {
+                    self.add_approval(Approval::Device {
+                        device: pending.device.clone(),
+                        name: pending.name.clone(),
+                    });
+                }
+                self.pending_approvals.retain(|approval| {
+                    !matches!(approval, Approval::Device { device, .. }
+                        if removed.iter().any(|pending| pending.device == *device))
+                });
+            }
+
+            // Accepting a device or folder saves the config, which has been
+            // read again by now.
+            EventsResponseData::ConfigSaved {} => {
+                let mut approvals = mem::take(&mut self.pending_approvals);
+                approvals.retain(|approval| !self.is_approved(approval));
+                self.pending_approvals = approvals;
+            }
+        });
+
+        if let Some(entry) = response.last() {
+            self.since = entry.id;
+            self.last_event_at = now;
+        }
+
+        let reconnected = response
+            .iter()
+            .filter_map(|entry| match &entry.data {
+                EventsResponseData::DeviceConnected { id } => Some(id),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+        if !reconnected.is_empty() {
+            self.poll_reconnected_completion(&reconnected, now)?;
+        }
+
+        if !self.args.completion_events_only {
+            // Pruning is only a fallback for missed DeviceDisconnected events,
+            // so a proxy that does not expose connections must not stop us.
+            match self.refresh_connected_devices() {
+                Ok(()) => self.connections_unavailable = false,
+                Err(err) if !self.connections_unavailable => {
+                    log::warn!("Cannot read connections, relying on events only: {:#}", err);
+                    self.connections_unavailable = true;
+                }
+                Err(err) => log::debug!("Cannot read connections: {:#}", err),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn run_on_complete(&self, device: &DeviceID, folder: &FolderID) {
+        let Some(command) = &self.args.on_complete else {
+            return;
+        };
+
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .env("SYNCTHING_DEVICE", self.device_name(device))
+            .env("SYNCTHING_DEVICE_ID", device.as_str())
+            .env("SYNCTHING_FOLDER", self.folder_name(folder))
+            .env("SYNCTHING_FOLDER_ID", folder.as_str())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn();
+
+        match child {
+            // Reap the hook in the background so a slow one cannot hold up the bar.
+            Ok(mut child) => {
+                thread::spawn(move || child.wait());
+            }
+            Err(err) => log::warn!("Failed to run --on-complete hook: {}", err),
+        }
+    }
+
+    /// Self-heals from missed events by forgetting progress that has not been
+    /// updated for `--pending-ttl`.
+    fn expire_stale_pending(&mut self) {
+        let Some(ttl) = self.args.pending_ttl else {
+            return;
+        };
+
+        let now = self.clock.now();
+        self.pending.iter_mut().for_each(|(device, folders)| {
+            folders.retain(|folder, progress| {
+                let fresh = now.duration_since(progress.updated_at) < ttl;
+                if !fresh {
+                    log::debug!(
+                        "Expiring stale progress of {} on {}",
+                        folder.as_str(),
+                        device.as_str()
+                    );
+                }
+                fresh
+            });
+        });
+        self.pending.retain(|_, folders| !folders.is_empty());
+    }
+
+    /// The last file operation stays relevant only while its folder is still
+    /// syncing somewhere.
+    fn forget_finished_disk_activity(&mut self) {
+        let finished = self.disk_activity.as_ref().is_some_and(|activity| {
+            !self
+                .pending
+                .values()
+                .any(|folders| folders.contains_key(&activity.folder))
+        });
+        if finished {
+            self.disk_activity = None;
+        }
+    }
+
+    fn subscribed_events(&self) -> Vec<&'static str> {
+        EVENT_TYPES
+            .iter()
+            .filter(|event| event.stream == EventStream::Main && (event.enabled)(&self.args))
+            .map(|event| event.name)
+            .collect()
+    }
+
+    pub fn print_event_types(args: &Args) {
+        for event in EVENT_TYPES {
+            println!(
+                "{:<22} {:<11} {}",
+                event.name,
+                if (event.enabled)(args) {
+                    "subscribed"
+                } else {
+                    "available"
+                },
+                event.description
+            );
+        }
+    }
+
+    /// Polls the file-level event stream without waiting, so the main event
+    /// long-poll keeps deciding the update cadence.
+    fn get_disk_events(&mut self) -> Result<()> {
+        let response = self
+            .client
+            .get(&format!(
+                "rest/events/disk?since={}&timeout=0",
+                self.disk_since
+            ))
+            .and_then(|response| response.json::<DiskEventsResponse>())
+            .with_context(|| {
+                format!("Failed to read rest/events/disk since {}", self.disk_since)
+            })?;
+
+        if let Some(entry) = response.last() {
+            self.disk_since = entry.id;
+            self.disk_activity = Some(DiskActivity {
+                incoming: matches!(entry.event_type, DiskEventType::RemoteChangeDetected),
+                path: entry.data.path.clone(),
+                folder: entry.data.folder.clone(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Events only report changes, so ask for the completion of every shared
+    /// folder up front, at most `--max-concurrent-requests` at a time.
+    fn bootstrap_completion(&mut self) -> Result<()> {
+        self.refresh_devices_and_folders()?;
+
+        let now = self.clock.now();
+        let targets = self.completion_targets(now);
+        log::debug!("Bootstrapping completion of {} folders", targets.len());
+
+        let results = self.fetch_completion(&targets)?;
+        self.apply_completion(results, now);
+
+        Ok(())
+    }
+
+    /// State changes are only reported as they happen, so folders that were
+    /// already scanning or syncing at startup have to be asked about.
+    fn seed_folder_states(&mut self) -> Result<()> {
+        if self.folders.is_empty() {
+            self.refresh_devices_and_folders()?;
+        }
+
+        let mut folders = self.folders.keys().cloned().collect::<Vec<_>>();
+        folders.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        for folder in folders {
+            let status = self
+                .client
+                .get(&format!(
+                    "rest/db/status?folder={}",
+                    encode(folder.as_str())
+                ))
+                .and_then(|response| response.json::<DbStatusResponse>())
+                .with_context(|| format!("Failed to read rest/db/status of {}", folder.as_str()))?;
+            if status.state != "idle" {
+                self.folder_states.insert(folder, status.state);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// With `--poll-interval`, every cycle builds `pending` anew from the
+    /// completion of every shared folder.
+    fn poll_all_completion(&mut self) -> Result<()> {
+        self.refresh_devices_and_folders()?;
+        if !self.args.completion_events_only {
+            self.refresh_connected_devices()?;
+        }
+
+        let now = self.clock.now();
+        let targets = self.completion_targets(now);
+        log::debug!("Polling completion of {} folders", targets.len());
+
+        let results = self.fetch_completion(&targets)?;
+        // Folders no longer shared with a device are not asked about again.
+        self.pending.retain(|device, folders| {
+            folders.retain(|folder, _| targets.iter().any(|(d, f)| (d, f) == (&*device, &*folder)));
+            !folders.is_empty()
+        });
+        self.apply_completion(results, now);
+
+        Ok(())
+    }
+
+    /// Every folder shared with a remote device, unless it was in sync there
+    /// recently enough to not ask.
+    fn completion_targets(&self, now: Instant) -> Vec<(DeviceID, FolderID)> {
+        self.folder_devices
+            .iter()
+            .flat_map(|(folder, devices)| devices.iter().map(move |device| (device, folder)))
+            .filter(|(device, _)| self.my_id.as_ref() != Some(*device))
+            .filter(|(device, folder)| !self.is_quiet(device, folder, now))
+            .map(|(device, folder)| (device.clone(), folder.clone()))
+            .collect()
+    }
+
+    /// A broken event stream would freeze the shown progress, so once no events
+    /// arrived for `--completion-poll-fallback` ask for the pending folders.
+    fn poll_stale_completion(&mut self) -> Result<()> {
+        let now = self.clock.now();
+        if self.fallback_wait(now) != Some(Duration::ZERO) {
+            return Ok(());
+        }
+
+        let targets = self
+            .pending
+            .iter()
+            .flat_map(|(device, folders)| {
+                folders
+                    .keys()
+                    .map(move |folder| (device.clone(), folder.clone()))
+            })
+            .collect::<Vec<_>>();
+        log::debug!(
+            "No events for a while, polling completion of {} folders",
+            targets.len()
+        );
+
+        let results = self.fetch_completion(&targets)?;
+        self.apply_completion(results, now);
+        self.last_event_at = now;
+
+        Ok(())
+    }
+
+    /// A device that comes back mid-transfer may take a while to send a
+    /// completion event, so ask for the folders it shares right away.
+    fn poll_reconnected_completion(
+        &mut self,
+        devices: &HashSet<&DeviceID>,
+        now: Instant,
+    ) -> Result<()> {
+        let targets = self
+            .folder_devices
+            .iter()
+            .flat_map(|(folder, shared)| shared.iter().map(move |device| (device, folder)))
+            .filter(|(device, _)| devices.contains(device))
+            .map(|(device, folder)| (device.clone(), folder.clone()))
+            .collect::<Vec<_>>();
+        log::debug!(
+            "Devices reconnected, polling completion of {} folders",
+            targets.len()
+        );
+
+        let results = self.fetch_completion(&targets)?;
+        self.apply_completion(results, now);
+
+        Ok(())
+    }
+
+    /// How long until the completion poll fallback is due, if it applies.
+    fn fallback_wait(&self, now: Instant) -> Option<Duration> {
+        let period = self.args.completion_poll_fallback?;
+        if self.pending.values().all(HashMap::is_empty) {
+            return None;
+        }
+
+        Some(period.saturating_sub(now.duration_since(self.last_event_at)))
+    }
+
+    /// Without a device, `rest/db/completion` tells how much of every folder
+    /// this device has, as one figure.
+    fn poll_local_completion(&mut self) -> Result<()> {
+        // Only for the name heading the tooltip.
+        if self.devices.is_empty() {
+            self.refresh_devices_and_folders()?;
+        }
+
+        let completion: DbCompletionResponse = self
+            .client
+            .get("rest/db/completion")
+            .and_then(|response| Ok(response.json::<DbCompletionBody>()?.into()))
+            .context("Failed to read rest/db/completion")?;
+        let was_unsynced = self.unsynced_local_completion().is_some();
+        self.local_completion = Some(FolderProgress::new(
+            completion
+                .items
+                .completion(completion.completion, self.args.completion_mode),
+            Some(completion.need_bytes),
+            completion.global_bytes,
+            completion.items,
+            self.clock.now(),
+        ));
+        if was_unsynced && self.unsynced_local_completion().is_none() {
+            self.last_synced = Some(self.clock.now_system());
+        }
+
+        Ok(())
+    }
+
+    /// Queries `rest/db/completion` for every pair, at most
+    /// `--max-concurrent-requests` at a time.
+    fn fetch_completion(
+        &self,
+        targets: &[(DeviceID, FolderID)],
+    ) -> Result<Vec<(DeviceID, FolderID, DbCompletionResponse)>> {
+        let next = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            let workers = (0..self.args.max_concurrent_requests.min(targets.len()))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut results = Vec::new();
+                        while let Some((device, folder)) =
+                            targets.get(next.fetch_add(1, Ordering::Relaxed))
+                        {
+                            let path = format!(
+                                "rest/db/completion?device={}&folder={}",
+                                encode(device.as_str()),
+                                encode(folder.as_str())
+                            );
+                            let completion = self
+                                .client
+                                .get(&path)
+                                .and_then(|response| {
+                                    Ok(response.json::<DbCompletionBody>()?.into())
+                                })
+                                .with_context(|| {
+                                    format!(
+                                        "Failed to read rest/db/completion of {} on {}",
+                                        folder.as_str(),
+                                        device.as_str()
+                                    )
+                                });
+                            results
+                                .push(completion.map(|completion| {
+                                    (device.clone(), folder.clone(), completion)
+                                }));
+                        }
+                        results
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("completion worker panicked"))
+                .collect()
+        })
+    }
+
+    fn apply_completion(
+        &mut self,
+        results: Vec<(DeviceID, FolderID, DbCompletionResponse)>,
+        now: Instant,
+    ) {
+        results
+            .into_iter()
+            .for_each(|(device, folder, completion)| {
+                let progress = FolderProgress::new(
+                    completion
+                        .items
+                        .completion(completion.completion, self.args.completion_mode),
+                    Some(completion.need_bytes),
+                    completion.global_bytes,
+                    completion.items,
+                    now,
+                );
+                if self.is_complete(&folder, progress.completion) {
+                    let was_pending = self
+                        .pending
+                        .get_mut(&device)
+                        .and_then(|folders| folders.remove(&folder))
+                        .is_some();
+                    if was_pending {
+                        self.last_synced = Some(self.clock.now_system());
+                    }
+                } else {
+                    self.pending
+                        .entry(device.clone())
+                        .or_default()
+                        .insert(folder.clone(), progress);
+                }
+                self.record_quiet(&device, &folder, progress.completion, now);
+                self.folder_sizes.insert((device, folder), progress);
+            });
+    }
+
+    /// Whether the folder is far enough along to stop showing it, by its own
+    /// `--folder-threshold` or else `--completion-threshold`.
+    fn is_complete(&self, folder: &FolderID, completion: ProgressPct) -> bool {
+        let threshold = self
+            .args
+            .folder_threshold
+            .iter()
+            .find(|(id, _)| id == folder.as_str())
+            .map_or(self.args.completion_threshold, |(_, threshold)| *threshold);
+
+        completion.0 >= threshold
+    }
+
+    /// Whether the folder was fully synced on the device less than
+    /// `--completion-cache-ttl` ago, so that asking again is pointless.
+    fn is_quiet(&self, device: &DeviceID, folder: &FolderID, now: Instant) -> bool {
+        let Some(ttl) = self.args.completion_cache_ttl else {
+            return false;
+        };
+
+        self.quiet_since
+            .get(&(device.clone(), folder.clone()))
+            .is_some_and(|since| now.duration_since(*since) < ttl)
+    }
+
+    fn record_quiet(
+        &mut self,
+        device: &DeviceID,
+        folder: &FolderID,
+        completion: ProgressPct,
+        now: Instant,
+    ) {
+        let key = (device.clone(), folder.clone());
+        if completion == ProgressPct(100.) {
+            self.quiet_since.insert(key, now);
+        } else {
+            self.quiet_since.remove(&key);
+        }
+    }
+
+    fn refresh_connected_devices(&mut self) -> Result<()> {
+        let response = self
+            .client
+            .get("rest/system/connections")
+            .and_then(|response| response.json::<SystemConnectionsResponse>())
+            .context("Failed to read rest/system/connections")?;
+
+        if !self.args.keep_disconnected {
+            response
+                .connections
+                .iter()
+                .filter(|(_, v)| !v.is_connected())
+                .for_each(|(id, _)| {
+                    self.pending.remove(id);
+                });
+        }
+        self.connected = response
+            .connections
+            .iter()
+            .map(|(id, device)| (id.clone(), device.is_connected()))
+            .collect();
+
+        if self.args.show_rate {
+            let now = self.clock.now();
+            for (id, device) in &response.connections {
+                let bytes_total = device.bytes_total();
+                self.transfer_rates
+                    .entry(id.clone())
+                    .and_modify(|rate| rate.sample(bytes_total, now))
+                    .or_insert(TransferRate {
+                        bytes_total,
+                        sampled_at: now,
+                        bytes_per_sec: None,
+                    });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn refresh_devices_and_folders(&mut self) -> Result<()> {
+        log::debug!("Refreshing devices...");
+
+        let response = self
+            .client
+            .get("rest/system/config")
+            .and_then(|response| response.json::<SystemConfigResponse>())
+            .context("Failed to read rest/system/config")?;
+
+        // Syncthing can briefly serve an empty config while it is being
+        // reconfigured; keep the names we know instead of showing raw IDs.
+        if response.devices.is_empty() {
+            log::debug!("Config has no devices, keeping previous device names");
+        } else {
+            self.paused_devices = response
+                .devices
+                .iter()
+                .filter(|entry| entry.paused)
+                .map(|entry| entry.device_id.clone())
+                .collect();
+            self.devices = response
+                .devices
+                .into_iter()
+                .map(|entry| (entry.device_id, entry.name))
+                .collect();
+        }
+
+        if response.folders.is_empty() {
+            log::debug!("Config has no folders, keeping previous folder names");
+            return Ok(());
+        }
+
+        self.folder_devices = response
+            .folders
+            .iter()
+            .map(|entry| {
+                let devices = entry.devices.iter().map(|d| d.device_id.clone()).collect();
+                (entry.id.clone(), devices)
+            })
+            .collect();
+
+        self.folders = response
+            .folders
+            .into_iter()
+            .map(|entry| (entry.id, entry.label))
+            .collect();
+        if self.args.cache_names {
+            self.save_names();
+        }
+
+        Ok(())
+    }
+
+    /// The part of the status that comes from this instance alone. The
+    /// overall figures are left to `Runners::print_status`.
+    /// `entries` are this instance's `displayed_pending()`, worked out once per
+    /// print.
+    fn write_instance(&self, entries: &[PendingEntry], text: &mut String, tooltip: &mut String) {
+        if self.args.mode == Mode::Local {
+            self.write_local_completion(text, tooltip);
+            self.write_pending_approvals(tooltip);
+            return;
+        }
+        if self.args.cluster_view {
+            self.write_cluster_view(entries, text, tooltip);
+        } else {
+            self.write_device_view(entries, text, tooltip);
+        }
+        self.write_local_progress(text, tooltip);
+        self.write_orphan_errors(entries, tooltip);
+
+        if let Some(activity) = self.disk_activity.as_ref().filter(|_| !tooltip.is_empty()) {
+            let file_name = Path::new(&activity.path)
+                .file_name()
+                .map_or(activity.path.as_str(), |name| {
+                    name.to_str().unwrap_or_default()
+                });
+            let _ = write!(
+                tooltip,
+                "\n{} {} ({})",
+                if activity.incoming {
+                    "downloading"
+                } else {
+                    "uploading"
+                },
+                self.markup(file_name),
+                self.markup(self.folder_name(&activity.folder))
+            );
+        }
+
+        if let Some(template) = &self.args.text_format {
+            text.clear();
+            for &(device, folder, progress) in entries {
+                push_separator(text, " | ");
+                template.render_into(text, |field| {
+                    self.entry_field(field, device, folder, progress, self.args.text_bytes)
+                });
+            }
+        } else if self.args.bucketed_text {
+            text.clear();
+            self.write_bucketed_text(entries, text);
+        }
+        self.write_pending_approvals(tooltip);
+    }
+
+    /// Counts of folders being pulled, being pulled from us by devices that are
+    /// behind, and being scanned, leaving out the empty buckets.
+    fn write_bucketed_text(&self, entries: &[PendingEntry], text: &mut String) {
+        let in_state = |states: &[&str]| {
+            self.folder_states
+                .values()
+                .filter(|state| states.contains(&state.as_str()))
+                .count()
+        };
+        let downloading = in_state(&["sync-preparing", "syncing"]);
+        let scanning = in_state(&["scan-waiting", "scanning"]);
+        let uploading = entries
+            .iter()
+            .filter(|(device, _, _)| self.my_id.as_ref() != Some(*device))
+            .map(|(_, folder, _)| folder)
+            .collect::<HashSet<_>>()
+            .len();
+
+        [
+            ("\u{2b07}", downloading),
+            ("\u{2b06}", uploading),
+            ("\u{27f3}", scanning),
+        ]
+        .into_iter()
+        .filter(|(_, count)| *count > 0)
+        .for_each(|(icon, count)| {
+            push_separator(text, " ");
+            let _ = write!(text, "{}{}", icon, count);
+        });
+    }
+
+    /// Whether every remote device is paused, which would otherwise look just
+    /// like everything being in sync.
+    fn is_paused_globally(&self) -> bool {
+        let mut remote = self
+            .devices
+            .keys()
+            .filter(|device| self.my_id.as_ref() != Some(*device))
+            .peekable();
+        remote.peek().is_some() && remote.all(|device| self.paused_devices.contains(device))
+    }
+
+    /// How many remote devices are connected, out of how many.
+    fn connected_devices(&self) -> (usize, usize) {
+        self.connected
+            .iter()
+            .filter(|(device, _)| self.my_id.as_ref() != Some(*device))
+            .fold((0, 0), |(connected, total), (_, is_connected)| {
+                (connected + usize::from(*is_connected), total + 1)
+            })
+    }
+
+    /// How many of the configured remote devices have nothing left to sync,
+    /// out of how many.
+    fn synced_devices(&self, entries: &[PendingEntry]) -> (usize, usize) {
+        let devices = self
+            .devices
+            .keys()
+            .filter(|device| self.my_id.as_ref() != Some(*device))
+            .collect::<Vec<_>>();
+        let synced = devices
+            .iter()
+            .filter(|device| !entries.iter().any(|(d, _, _)| d == *device))
+            .count();
+
+        (synced, devices.len())
+    }
+
+    /// The waybar `class` to style the module by.
+    fn status_class(&self) -> &'static str {
+        match self.failure {
+            Some(class) => class,
+            None if self.has_errors() => "error",
+            None if !self.pending_approvals.is_empty() => "attention",
+            None if self.is_scanning() => "scanning",
+            None if self.is_idle() => "idle",
+            None => "syncing",
+        }
+    }
+
+    fn add_approval(&mut self, approval: Approval) {
+        if !self.pending_approvals.contains(&approval) {
+            self.pending_approvals.push(approval);
+        }
+    }
+
+    fn is_approved(&self, approval: &Approval) -> bool {
+        match approval {
+            Approval::Device { device, .. } => self.devices.contains_key(device),
+            Approval::Folder { device, folder, .. } => self
+                .folder_devices
+                .get(folder)
+                .is_some_and(|devices| devices.contains(device)),
+        }
+    }
+
+    /// The count in the text is left to `Runners::print_status`, after
+    /// whichever layout rewrote the text.
+    fn write_pending_approvals(&self, tooltip: &mut String) {
+        if self.pending_approvals.is_empty() {
+            return;
+        }
+
+        push_separator(tooltip, "\n");
+        self.write_header(tooltip, "Awaiting approval");
+        for approval in &self.pending_approvals {
+            tooltip.push_str("\n  ");
+            match approval {
+                Approval::Device { device, name } => {
+                    let name = if name.is_empty() {
+                        device.short()
+                    } else {
+                        name.as_str()
+                    };
+                    let _ = write!(tooltip, "{} wants to connect", self.markup(name));
+                }
+                Approval::Folder {
+                    device,
+                    folder,
+                    label,
+                } => {
+                    let label = if label.is_empty() {
+                        folder.as_str()
+                    } else {
+                        label.as_str()
+                    };
+                    let _ = write!(
+                        tooltip,
+                        "{} shares {}",
+                        self.markup(self.device_name(device)),
+                        self.markup(label)
+                    );
+                }
+            }
+        }
+    }
+
+    fn has_errors(&self) -> bool {
+        self.folder_errors
+            .keys()
+            .any(|folder| self.is_local_selected(folder))
+    }
+
+    /// Nothing is moving in either direction.
+    fn is_idle(&self) -> bool {
+        if self.args.mode == Mode::Local {
+            return self.unsynced_local_completion().is_none();
+        }
+        !self.has_displayed_pending() && self.busy_local_folders().is_empty()
+    }
+
+    /// With `--show-scanning`, a folder is looking for changes while no data
+    /// is being transferred.
+    fn is_scanning(&self) -> bool {
+        self.args.show_scanning
+            && !self.has_displayed_pending()
+            && self
+                .busy_local_folders()
+                .iter()
+                .all(|(_, local)| local.is_scanning())
+            && self
+                .folder_states
+                .iter()
+                .any(|(folder, state)| state.starts_with("scan") && self.is_local_selected(folder))
+    }
+
+    /// Every displayed entry with both IDs and names, for widgets that lay out
+    /// the progress themselves instead of showing `text`.
+    fn full_status_entries<'a>(&'a self, entries: &[PendingEntry<'a>]) -> Vec<FullStatusEntry<'a>> {
+        entries
+            .iter()
+            .map(|&(device, folder, progress)| FullStatusEntry {
+                device: device.as_str(),
+                device_name: self.device_name(device),
+                folder: folder.as_str(),
+                folder_name: self.folder_name(folder),
+                completion: progress.completion.0,
+                need_bytes: progress.need_bytes.map(|bytes| bytes.0),
+                global_bytes: progress.global_bytes.0,
+            })
+            .collect()
+    }
+
+    /// Everything tracked in `pending`, as reported by Syncthing.
+    fn pending_json(&self) -> serde_json::Map<String, serde_json::Value> {
+        self.pending
+            .iter()
+            .map(|(device, folders)| {
+                let folders = folders
+                    .iter()
+                    .map(|(folder, progress)| {
+                        let progress = serde_json::json!({
+                            "completion": progress.completion.0,
+                            "needBytes": progress.need_bytes.map(|bytes| bytes.0),
+                            "globalBytes": progress.global_bytes.0,
+                        });
+                        (folder.as_str().to_string(), progress)
+                    })
+                    .collect::<serde_json::Map<_, _>>();
+                (device.as_str().to_string(), folders.into())
+            })
+            .collect()
+    }
+
+    /// What the one overall completion is made of, so that the text,
+    /// `percentage` and `aggregate` never disagree. With several instances,
+    /// all of theirs are combined at once.
+    fn aggregate_parts<'a>(&'a self, entries: &[PendingEntry<'a>]) -> Vec<&'a FolderProgress> {
+        if self.args.mode == Mode::Local {
+            return self
+                .local_completion
+                .as_ref()
+                .filter(|_| self.args.show_global_progress)
+                .or(self.unsynced_local_completion())
+                .into_iter()
+                .collect();
+        }
+        if self.args.show_global_progress {
+            // Every folder on every device Syncthing reported on, synced or not.
+            let progresses = self.folder_sizes.values().collect::<Vec<_>>();
+            if progresses
+                .iter()
+                .all(|progress| progress.global_bytes.0 == 0)
+            {
+                return Vec::new();
+            }
+            return progresses;
+        }
+
+        entries
+            .iter()
+            .map(|(_, _, progress)| *progress)
+            .chain(
+                self.busy_local_folders()
+                    .into_iter()
+                    .filter(|(_, local)| !local.is_scanning())
+                    .map(|(_, local)| &local.progress),
+            )
+            .collect()
+    }
+
+    fn write_device_view(&self, entries: &[PendingEntry], text: &mut String, tooltip: &mut String) {
+        let segments = if self.args.aggregate_across_devices_in_text {
+            let mut folders: Vec<(&FolderID, Vec<&FolderProgress>)> = Vec::new();
+            for (_, folder, progress) in entries {
+                match folders.iter_mut().find(|(f, _)| f == folder) {
+                    Some((_, progresses)) => progresses.push(progress),
+                    None => folders.push((folder, vec![progress])),
+                }
+            }
+            folders
+                .iter()
+                .filter_map(|(_, progresses)| Aggregate::of(progresses, self.args.aggregate))
+                .collect::<Vec<_>>()
+        } else {
+            entries
+                .iter()
+                .map(|(_, _, progress)| Aggregate::from(*progress))
+                .collect()
+        };
+
+        if self.args.collapse_identical_percent {
+            self.write_collapsed_text(&segments, text);
+        } else {
+            for segment in &segments {
+                push_separator(text, " | ");
+                self.write_segment(text, segment);
+            }
+        }
+
+        if self.device_groups.is_empty() {
+            self.write_device_entries(tooltip, entries, "");
+            return;
+        }
+
+        // Ungrouped devices go last, under the index past every named group.
+        let other = self.args.device_group.len();
+        let group_of = |device: &DeviceID| {
+            self.device_groups
+                .get(device.as_str())
+                .copied()
+                .unwrap_or(other)
+        };
+        for group in 0..=other {
+            let members = entries
+                .iter()
+                .filter(|(device, _, _)| group_of(device) == group)
+                .copied()
+                .collect::<Vec<_>>();
+            if members.is_empty() {
+                continue;
+            }
+
+            push_separator(tooltip, "\n");
+            match self.args.device_group.get(group) {
+                Some((name, _)) => self.write_header(tooltip, name),
+                None => self.write_header(tooltip, "Other"),
+            }
+            self.write_device_entries(tooltip, &members, "  ");
+        }
+    }
+
+    /// The folders of every device under a header with its name. Entries from
+    /// `--tooltip-format` stay one per line, as the template decides on names.
+    fn write_device_entries(&self, tooltip: &mut String, entries: &[PendingEntry], indent: &str) {
+        let mut current = None;
+        for &(device, folder, progress) in entries {
+            let templated = self.args.tooltip_format.is_some();
+            if !templated && current != Some(device) {
+                push_separator(tooltip, "\n");
+                tooltip.push_str(indent);
+                self.write_header(tooltip, self.device_name(device));
+                if self.connected.get(device) == Some(&false) {
+                    tooltip.push_str(" (disconnected)");
+                }
+                current = Some(device);
+            }
+
+            push_separator(tooltip, "\n");
+            tooltip.push_str(indent);
+            if !templated {
+                tooltip.push_str("  ");
+            }
+            self.write_tooltip_entry(tooltip, device, folder, progress);
+        }
+    }
+
+    /// A device or group name above the lines that belong to it.
+    fn write_header(&self, tooltip: &mut String, name: &str) {
+        if self.args.no_markup {
+            tooltip.push_str(name);
+        } else {
+            let _ = write!(tooltip, "<b>{}</b>", self.markup(name));
+        }
+    }
+
+    fn markup<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        escape_markup(&self.args, text)
+    }
+
+    fn write_tooltip_entry(
+        &self,
+        tooltip: &mut String,
+        device: &DeviceID,
+        folder: &FolderID,
+        progress: &FolderProgress,
+    ) {
+        if let Some(template) = &self.args.tooltip_format {
+            template.render_into(tooltip, |field| {
+                let value =
+                    self.entry_field(field, device, folder, progress, self.args.tooltip_bytes);
+                self.markup(&value).into_owned()
+            });
+            self.write_errors(tooltip, folder);
+            return;
+        }
+
+        let folder_name = self.folder_name(folder);
+        tooltip.push_str(&self.markup(folder_name));
+        // Pad by display width so wide CJK names keep the columns aligned.
+        pad(tooltip, folder_name.width(), 10);
+        self.write_figures(tooltip, progress, Some(device));
+        self.write_errors(tooltip, folder);
+    }
+
+    /// The percentage and what is left, with `--show-rate` also how fast
+    /// `device` is getting there.
+    fn write_figures(
+        &self,
+        tooltip: &mut String,
+        progress: &FolderProgress,
+        device: Option<&DeviceID>,
+    ) {
+        let _ = write!(
+            tooltip,
+            " {}%",
+            progress.completion.rounded(self.args.percent_round)
+        );
+        let need = format_need(
+            progress.need_bytes,
+            progress.need_items,
+            self.args.tooltip_bytes,
+            self.args.si_units,
+        );
+        let Some(need) = need else {
+            return;
+        };
+
+        let _ = write!(tooltip, " ({}", need);
+        if let (Some(device), Some(need_bytes)) = (device, progress.need_bytes) {
+            if self.args.show_rate {
+                self.write_rate(tooltip, device, need_bytes);
+            }
+        }
+        tooltip.push(')');
+    }
+
+    /// Marks a folder that failed to sync with its latest error.
+    fn write_errors(&self, tooltip: &mut String, folder: &FolderID) {
+        let Some([error, rest @ ..]) = self.folder_errors.get(folder).map(Vec::as_slice) else {
+            return;
+        };
+
+        let _ = write!(
+            tooltip,
+            " \u{26a0} {}: {}",
+            self.markup(&error.path),
+            self.markup(&error.error)
+        );
+        if !rest.is_empty() {
+            let _ = write!(tooltip, " (+{} more)", rest.len());
+        }
+    }
+
+    /// Folders with errors that no other tooltip line is about, so that
+    /// the errors are never hidden.
+    fn write_orphan_errors(&self, entries: &[PendingEntry], tooltip: &mut String) {
+        let shown = entries
+            .iter()
+            .map(|(_, folder, _)| *folder)
+            .chain(
+                self.busy_local_folders()
+                    .into_iter()
+                    .map(|(folder, _)| folder),
+            )
+            .collect::<HashSet<_>>();
+        let mut folders = self
+            .folder_errors
+            .keys()
+            .filter(|folder| !shown.contains(folder) && self.is_local_selected(folder))
+            .collect::<Vec<_>>();
+        folders.sort_by_key(|folder| self.folder_name(folder));
+
+        for folder in folders {
+            push_separator(tooltip, "\n");
+            tooltip.push_str(&self.markup(self.folder_name(folder)));
+            self.write_errors(tooltip, folder);
+        }
+    }
+
+    /// How fast the device has been transferring, and how long the folder
+    /// would take at that pace.
+    fn write_rate(&self, tooltip: &mut String, device: &DeviceID, need_bytes: Bytes) {
+        let rate = self
+            .transfer_rates
+            .get(device)
+            .and_then(|rate| rate.bytes_per_sec)
+            .filter(|rate| *rate > 0.);
+        let Some(rate) = rate else {
+            tooltip.push_str(", ETA: \u{2014}");
+            return;
+        };
+
+        let _ = write!(
+            tooltip,
+            ", {}/s, ETA: {}",
+            Bytes(rate as u64).format(self.args.tooltip_bytes, self.args.si_units),
+            format_eta(Duration::from_secs_f64(need_bytes.0 as f64 / rate))
+        );
+    }
+
+    /// Writes one segment per displayed percentage, so that folders which are
+    /// all at the same point collapse into `N folders at X%`.
+    fn write_collapsed_text(&self, segments: &[Aggregate], text: &mut String) {
+        let mut groups: Vec<(String, Vec<&Aggregate>)> = Vec::new();
+        for segment in segments {
+            let percent = segment
+                .completion
+                .rounded(self.args.percent_round)
+                .to_string();
+            match groups.iter_mut().find(|(p, _)| *p == percent) {
+                Some((_, group)) => group.push(segment),
+                None => groups.push((percent, vec![segment])),
+            }
+        }
+
+        for (percent, group) in groups {
+            push_separator(text, " | ");
+            match group.as_slice() {
+                [segment] => self.write_segment(text, segment),
+                _ => {
+                    self.write_icon(text);
+                    let _ = write!(text, "{} folders at {}%", group.len(), percent);
+                }
+            }
+        }
+    }
+
+    fn write_segment(&self, text: &mut String, segment: &Aggregate) {
+        self.write_icon(text);
+        let _ = write!(
+            text,
+            "{}%",
+            segment.completion.rounded(self.args.percent_round)
+        );
+        let need = format_need(
+            segment.need_bytes,
+            segment.need_items,
+            self.args.text_bytes,
+            self.args.si_units,
+        );
+        if let Some(need) = need {
+            let _ = write!(text, "/{}", need);
+        }
+    }
+
+    fn entry_field(
+        &self,
+        field: Field,
+        device: &DeviceID,
+        folder: &FolderID,
+        progress: &FolderProgress,
+        bytes: ByteFormat,
+    ) -> String {
+        match field {
+            Field::Device => self.device_name(device).to_string(),
+            Field::Folder => self.folder_name(folder).to_string(),
+            Field::Count => "1".to_string(),
+            _ => self.aggregate_field(field, &Aggregate::from(progress), 1, bytes),
+        }
+    }
+
+    fn aggregate_field(
+        &self,
+        field: Field,
+        aggregate: &Aggregate,
+        count: usize,
+        bytes: ByteFormat,
+    ) -> String {
+        let si = self.args.si_units;
+        match field {
+            Field::Icon => self.args.icon.clone(),
+            Field::Percent => aggregate
+                .completion
+                .rounded(self.args.percent_round)
+                .to_string(),
+            Field::NeedBytes => format_need(aggregate.need_bytes, aggregate.need_items, bytes, si)
+                .unwrap_or_default(),
+            Field::TotalBytes => aggregate.global_bytes.format(bytes, si),
+            Field::Count => count.to_string(),
+            // Rejected for the overall progress when the arguments are parsed.
+            Field::Device | Field::Folder => String::new(),
+            // Only available in `--idle-format`.
+            Field::Ago => String::new(),
+        }
+    }
+
+    /// Local folders that are being scanned or pulled, by folder name.
+    fn busy_local_folders(&self) -> Vec<(&FolderID, &LocalFolder)> {
+        let mut folders = self
+            .local_folders
+            .iter()
+            .filter(|(folder, local)| local.is_busy() && self.is_local_selected(folder))
+            .collect::<Vec<_>>();
+        folders.sort_by(|(a, _), (b, _)| self.folder_name(a).cmp(self.folder_name(b)));
+        folders
+    }
+
+    fn local_device_name(&self) -> &str {
+        match &self.my_id {
+            Some(device) => self.device_name(device),
+            None => "this device",
+        }
+    }
+
+    /// The `--mode local` completion, unless this device is in sync.
+    fn unsynced_local_completion(&self) -> Option<&FolderProgress> {
+        self.local_completion
+            .as_ref()
+            .filter(|progress| progress.completion.0 < self.args.completion_threshold)
+    }
+
+    fn write_local_completion(&self, text: &mut String, tooltip: &mut String) {
+        let Some(progress) = self.unsynced_local_completion() else {
+            return;
+        };
+
+        self.write_segment(text, &Aggregate::from(progress));
+        self.write_header(tooltip, self.local_device_name());
+        tooltip.push_str("\n  All folders");
+        self.write_figures(tooltip, progress, None);
+    }
+
+    fn write_local_progress(&self, text: &mut String, tooltip: &mut String) {
+        let device_name = self.local_device_name();
+        for (index, (folder, local)) in self.busy_local_folders().into_iter().enumerate() {
+            push_separator(text, " | ");
+            if index == 0 {
+                push_separator(tooltip, "\n");
+                self.write_header(tooltip, device_name);
+            }
+            tooltip.push_str("\n  ");
+            let folder_name = self.folder_name(folder);
+            tooltip.push_str(&self.markup(folder_name));
+            pad(tooltip, folder_name.width(), 10);
+
+            if local.is_scanning() {
+                self.write_icon(text);
+                text.push_str("scanning");
+                tooltip.push_str(" scanning");
+            } else {
+                self.write_segment(text, &Aggregate::from(&local.progress));
+                self.write_figures(tooltip, &local.progress, None);
+            }
+            self.write_errors(tooltip, folder);
+        }
+    }
+
+    /// An empty `--icon` leaves no gap before the figures.
+    fn write_icon(&self, text: &mut String) {
+        if !self.args.icon.is_empty() {
+            let _ = write!(text, "{} ", self.args.icon);
+        }
+    }
+
+    fn write_cluster_view(
+        &self,
+        entries: &[PendingEntry],
+        text: &mut String,
+        tooltip: &mut String,
+    ) {
+        let mut clusters: HashMap<&FolderID, FolderCluster> = HashMap::new();
+        entries.iter().for_each(|&(_, folder, progress)| {
+            clusters
+                .entry(folder)
+                .and_modify(|cluster| {
+                    cluster.behind += 1;
+                    cluster.need_bytes = sum_known([cluster.need_bytes, progress.need_bytes]);
+                    if progress.completion.0 < cluster.min_completion.0 {
+                        cluster.min_completion = progress.completion;
+                    }
+                })
+                .or_insert(FolderCluster {
+                    behind: 1,
+                    min_completion: progress.completion,
+                    need_bytes: progress.need_bytes,
+                });
+        });
+
+        // Same order as the device view, rather than the hash map's.
+        let mut clusters = clusters.into_iter().collect::<Vec<_>>();
+        clusters.sort_by_cached_key(|(folder, _)| self.folder_name(folder).to_lowercase());
+
+        for (folder, cluster) in &clusters {
+            push_separator(text, " | ");
+            self.write_segment(
+                text,
+                &Aggregate {
+                    completion: cluster.min_completion,
+                    need_bytes: cluster.need_bytes,
+                    global_bytes: Bytes::default(),
+                    need_items: 0,
+                },
+            );
+
+            // A folder's device list always includes the local device.
+            let peers = self
+                .folder_devices
+                .get(*folder)
+                .map(|devices| devices.len().saturating_sub(1))
+                .unwrap_or(0)
+                .max(cluster.behind);
+
+            push_separator(tooltip, "\n");
+            let _ = write!(
+                tooltip,
+                "{}: {}/{} peers, min {}%",
+                self.markup(self.folder_name(folder)),
+                cluster.behind,
+                peers,
+                cluster.min_completion.rounded(self.args.percent_round)
+            );
+        }
+    }
+
+    /// Pending progress flattened to one entry per device and folder, without
+    /// the entries that should not be displayed.
+    fn displayed_pending(&self) -> Vec<PendingEntry<'_>> {
+        let mut entries = self.displayed_entries().collect::<Vec<_>>();
+
+        // Pinned devices come first in the order given, everything else by name.
+        entries.sort_by_cached_key(|(device, folder, _)| {
+            let pin = self
+                .args
+                .pin_device
+                .iter()
+                .position(|pinned| pinned == device.as_str());
+            (
+                pin.unwrap_or(usize::MAX),
+                self.device_name(device).to_lowercase(),
+                device.as_str(),
+                self.folder_name(folder).to_lowercase(),
+            )
+        });
+        entries
+    }
+
+    /// Whether anything is displayed, without sorting the entries for it.
+    fn has_displayed_pending(&self) -> bool {
+        self.displayed_entries().next().is_some()
+    }
+
+    fn displayed_entries(&self) -> impl Iterator<Item = PendingEntry<'_>> {
+        self.pending
+            .iter()
+            .flat_map(|(device, folders)| {
+                folders
+                    .iter()
+                    .map(move |(folder, progress)| (device, folder, progress))
+            })
+            .filter(|(device, folder, _)| {
+                !self.args.folders_from_devices || self.is_shared(device, folder)
+            })
+            .filter(|(device, folder, _)| self.is_selected(device, folder))
+    }
+
+    /// Whether `--include-*`/`--exclude-*` let the entry through, matching
+    /// either the ID or the name.
+    fn is_selected(&self, device: &DeviceID, folder: &FolderID) -> bool {
+        let matches = |patterns: &[String], id: &str, name: &str| {
+            patterns
+                .iter()
+                .any(|pattern| pattern == id || pattern == name)
+        };
+        let selected = |include: &[String], exclude: &[String], id: &str, name: &str| {
+            (include.is_empty() || matches(include, id, name)) && !matches(exclude, id, name)
+        };
+
+        selected(
+            &self.args.include_device,
+            &self.args.exclude_device,
+            device.as_str(),
+            self.device_name(device),
+        ) && selected(
+            &self.args.include_folder,
+            &self.args.exclude_folder,
+            folder.as_str(),
+            self.folder_name(folder),
+        )
+    }
+
+    /// Local folders count as pending on this device for the filters.
+    fn is_local_selected(&self, folder: &FolderID) -> bool {
+        self.my_id
+            .as_ref()
+            .is_none_or(|device| self.is_selected(device, folder))
+    }
+
+    fn is_shared(&self, device: &DeviceID, folder: &FolderID) -> bool {
+        self.folder_devices
+            .get(folder)
+            .is_none_or(|devices| devices.contains(device))
+    }
+
+    fn device_name<'a>(&'a self, device: &'a DeviceID) -> &'a str {
+        match self.devices.get(device) {
+            // Syncthing reports the ID as the name when none was set.
+            Some(name) if !name.as_str().is_empty() && name.as_str() != device.as_str() => {
+                name.as_str()
+            }
+            _ => match self.args.unnamed_devices {
+                UnnamedDevices::ShortId => device.short(),
+                UnnamedDevices::FullId => device.as_str(),
+            },
+        }
+    }
+
+    fn folder_name<'a>(&'a self, folder: &'a FolderID) -> &'a str {
+        self.folders
+            .get(folder)
+            .map(|v| v.as_str())
+            .unwrap_or(folder.as_str())
+    }
+}
+
+impl Runners {
+    pub fn new(args: Args) -> Result<Self> {
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        let runners = args
+            .instances()?
+            .into_iter()
+            .map(|(base_url, api_key)| {
+                let client = ApiClient::new(&args, base_url, api_key)?;
+                let label = base_url
+                    .split_once("://")
+                    .map_or(base_url, |(_, address)| address)
+                    .trim_end_matches('/')
+                    .to_string();
+                log::info!("Watching Syncthing at {}", label);
+                Ok(Runner::new(
+                    Box::new(client),
+                    clock.clone(),
+                    args.clone(),
+                    label,
+                ))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self::with_runners(args, clock, runners))
+    }
+
+    fn with_runners(args: Args, clock: Arc<dyn Clock>, runners: Vec<Runner>) -> Self {
+        Self {
+            args,
+            clock,
+            runners,
+            last_print: None,
+            print_deferred: false,
+            text_buffer: String::new(),
+            tooltip_buffer: String::new(),
+            instance_text_buffer: String::new(),
+            instance_tooltip_buffer: String::new(),
+            latest_status: Arc::default(),
+            last_line: RefCell::default(),
+        }
+    }
+
+    pub fn main_loop(&mut self) -> Result<()> {
+        if self.args.once {
+            for runner in &mut self.runners {
+                runner.run_once()?;
+            }
+            self.print_status();
+            return Ok(());
+        }
+        if self.args.print_on_signal {
+            self.spawn_print_trigger();
+        }
+        if self.runners[0].poll_interval().is_none() {
+            log::debug!(
+                "Subscribing to {}",
+                self.runners[0].subscribed_events().join(", ")
+            );
+        }
+
+        loop {
+            self.poll_instances()?;
+        }
+    }
+
+    /// Runs a cycle of every instance that is due, sleeping first when none
+    /// of them is.
+    fn poll_instances(&mut self) -> Result<()> {
+        // Only sleep when every instance is backing off or waiting for its
+        // next poll.
+        let now = self.clock.now();
+        let retry_wait = self
+            .runners
+            .iter()
+            .map(|runner| {
+                runner
+                    .retry_at
+                    .map_or(Duration::ZERO, |at| at.saturating_duration_since(now))
+            })
+            .min()
+            .unwrap_or_default();
+        self.clock.sleep(retry_wait);
+
+        for index in 0..self.runners.len() {
+            let now = self.clock.now();
+            if self.runners[index].retry_at.is_some_and(|at| at > now) {
+                continue;
+            }
+
+            let wait = self.events_wait(now);
+            match self.runners[index].run_cycle(wait) {
+                Ok(()) => {
+                    self.runners[index].succeeded();
+                    self.flush_status();
+                }
+                Err(err) if is_transient(&err) => {
+                    log::warn!("Request to Syncthing failed, retrying: {:#}", err);
+                    if let Some(timeout) = self.args.hang_timeout {
+                        if self.is_hung(timeout) {
+                            return Err(err.context(Hung(timeout)));
+                        }
+                    }
+                    let runner = &mut self.runners[index];
+                    runner.check_restart = true;
+                    runner.back_off();
+                    // A connection that died while the machine was suspended
+                    // only shows up as requests timing out.
+                    if is_timeout(&err) || runner.is_stale() {
+                        log::info!("Reconnecting to Syncthing at {}", runner.label);
+                        if let Err(err) = runner.client.reconnect() {
+                            log::warn!("Failed to reconnect: {:#}", err);
+                        }
+                    }
+                    if runner.failure.is_none() {
+                        runner.failure = Some(failure_class(&err));
+                        self.print_failure(&err);
+                    }
+                }
+                Err(err) if is_malformed(&err) => {
+                    log::warn!("Ignoring unexpected response from Syncthing: {:#}", err);
+                    self.runners[index].back_off();
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// One instance getting through shows the process itself is fine, even
+    /// while another stays unreachable.
+    fn is_hung(&self, timeout: Duration) -> bool {
+        let now = self.clock.now();
+        self.runners
+            .iter()
+            .all(|runner| now.duration_since(runner.last_success) >= timeout)
+    }
+
+    /// How long the next long-poll may last: in time to print a deferred
+    /// status, and short enough for the other instances to get their turn.
+    fn events_wait(&self, now: Instant) -> Duration {
+        let mut wait = self.args.poll_events_max_wait;
+        if self.runners.len() > 1 {
+            wait = wait.min(SHARED_POLL_WAIT);
+        }
+        match self.print_wait(now).filter(|_| self.print_deferred) {
+            Some(print_wait) => wait.min(print_wait),
+            None => wait,
+        }
+    }
+
+    /// Prints right away unless the last print was less than `--output-interval`
+    /// ago, in which case the latest state is printed once the interval passes.
+    fn flush_status(&mut self) {
+        let now = self.clock.now();
+        if self.print_wait(now).is_some() {
+            self.print_deferred = true;
+            return;
+        }
+
+        self.print_status();
+        self.last_print = Some(now);
+        self.print_deferred = false;
+    }
+
+    fn print_wait(&self, now: Instant) -> Option<Duration> {
+        let interval = self.args.output_interval?;
+        let elapsed = now.duration_since(self.last_print?);
+        interval.checked_sub(elapsed).filter(|wait| !wait.is_zero())
+    }
+
+    fn print_status(&mut self) {
+        let args = &self.args;
+        if args.show_when_paused_globally && self.runners.iter().all(Runner::is_paused_globally) {
+            self.write_status(&Status {
+                debug: None,
+                percentage: None,
+                text: "syncthing paused",
+                tooltip: "All remote devices are paused",
+                class: Some("paused"),
+            });
+            return;
+        }
+
+        // Reuse the buffers across prints, they are rebuilt from scratch each time.
+        let mut text = mem::take(&mut self.text_buffer);
+        let mut tooltip = mem::take(&mut self.tooltip_buffer);
+        let mut instance_text = mem::take(&mut self.instance_text_buffer);
+        let mut instance_tooltip = mem::take(&mut self.instance_tooltip_buffer);
+        text.clear();
+        tooltip.clear();
+
+        // Sorting the entries goes by name, so it is only done once per print.
+        let displayed = self
+            .runners
+            .iter()
+            .map(Runner::displayed_pending)
+            .collect::<Vec<_>>();
+        for (runner, entries) in self.runners.iter().zip(&displayed) {
+            instance_text.clear();
+            instance_tooltip.clear();
+            runner.write_instance(entries, &mut instance_text, &mut instance_tooltip);
+
+            if !instance_text.is_empty() {
+                push_separator(&mut text, " | ");
+                text.push_str(&instance_text);
+            }
+            if instance_tooltip.is_empty() {
+                continue;
+            }
+            push_separator(&mut tooltip, "\n");
+            if self.runners.len() == 1 {
+                tooltip.push_str(&instance_tooltip);
+                continue;
+            }
+            // Instances can have devices and folders of the same name.
+            let label = escape_markup(args, &runner.label);
+            if args.combine_instances_label == InstanceLabel::Grouped {
+                tooltip.push_str(&label);
+                for line in instance_tooltip.lines() {
+                    tooltip.push_str("\n  ");
+                    tooltip.push_str(line);
+                }
+                continue;
+            }
+            for (index, line) in instance_tooltip.lines().enumerate() {
+                if index > 0 {
+                    tooltip.push('\n');
+                }
+                let heading = !line.starts_with(' ');
+                match args.combine_instances_label {
+                    InstanceLabel::Prefix if heading => {
+                        let _ = write!(tooltip, "{}: {}", label, line);
+                    }
+                    InstanceLabel::Suffix if heading => {
+                        let _ = write!(tooltip, "{} ({})", line, label);
+                    }
+                    _ => tooltip.push_str(line),
+                }
+            }
+        }
+
+        let aggregate = Aggregate::of(
+            &self
+                .runners
+                .iter()
+                .zip(&displayed)
+                .flat_map(|(runner, entries)| runner.aggregate_parts(entries))
+                .collect::<Vec<_>>(),
+            args.aggregate,
+        )
+        .map(|aggregate| match args.mode {
+            // Synced folders count too, so their items left say nothing.
+            Mode::Remote if args.show_global_progress => Aggregate {
+                need_items: 0,
+                ..aggregate
+            },
+            _ => aggregate,
+        });
+        let shows_aggregate = args.show_global_progress || args.progress_bar;
+        // Formatting only depends on the arguments, which all instances share.
+        let formatter = &self.runners[0];
+
+        if let Some(template) = &args.aggregate_format {
+            text.clear();
+            if let Some(aggregate) = &aggregate {
+                let count = displayed.iter().map(Vec::len).sum();
+                template.render_into(&mut text, |field| {
+                    formatter.aggregate_field(field, aggregate, count, args.text_bytes)
+                });
+            }
+        } else if args.text_format.is_some() {
+            // Written per entry by each instance.
+        } else if shows_aggregate {
+            text.clear();
+            if let Some(aggregate) = &aggregate {
+                formatter.write_segment(&mut text, aggregate);
+            }
+        } else if args.minimal {
+            text.clear();
+            if let Some(aggregate) = &aggregate {
+                formatter.write_icon(&mut text);
+                let _ = write!(
+                    text,
+                    "{}%",
+                    aggregate.completion.rounded(args.percent_round)
+                );
+            }
+        } else if !args.per_folder_segments() && !args.bucketed_text {
+            // Folders that are only being scanned keep their own segments.
+            if let Some(aggregate) = &aggregate {
+                text.clear();
+                formatter.write_segment(&mut text, aggregate);
+            }
+        }
+
+        if let Some(max_segments) = args.max_segments {
+            limit_segments(&mut text, max_segments);
+        }
+
+        let approvals = self
+            .runners
+            .iter()
+            .map(|runner| runner.pending_approvals.len())
+            .sum::<usize>();
+        if approvals > 0 {
+            push_separator(&mut text, " | ");
+            let _ = write!(text, "\u{26a0} {}", approvals);
+        }
+
+        if args.show_synced_ratio {
+            let (synced, devices) = self
+                .runners
+                .iter()
+                .zip(&displayed)
+                .map(|(runner, entries)| runner.synced_devices(entries))
+                .fold((0, 0), |(synced, devices), (s, d)| {
+                    (synced + s, devices + d)
+                });
+            push_separator(&mut text, " | ");
+            let _ = write!(text, "{}/{} synced", synced, devices);
+            push_separator(&mut tooltip, "\n");
+            let _ = write!(tooltip, "{}/{} devices synced", synced, devices);
+        }
+
+        if args.show_connected {
+            let (connected, devices) = self
+                .runners
+                .iter()
+                .map(Runner::connected_devices)
+                .fold((0, 0), |(connected, total), (c, t)| {
+                    (connected + c, total + t)
+                });
+            // With everyone online and nothing to do, the count is just noise.
+            if connected < devices || !self.runners.iter().all(Runner::is_idle) {
+                push_separator(&mut text, " | ");
+                let _ = write!(text, "{}/{} online", connected, devices);
+            } else if text.is_empty() {
+                text.push_str(&args.icon);
+            }
+            tooltip.insert_str(
+                0,
+                &format!(
+                    "{}/{} devices connected{}",
+                    connected,
+                    devices,
+                    if tooltip.is_empty() { "" } else { "\n" }
+                ),
+            );
+        }
+
+        if let Some(max_width) = args.text_max_length {
+            truncate_to_width(&mut text, max_width);
+        }
+        // An empty text leaves waybar nothing to hover for the tooltip.
+        if args.always_show_icon && text.is_empty() && !tooltip.is_empty() {
+            text.push_str(&args.icon);
+        }
+        if let Some(scanning_icon) = &args.scanning_icon {
+            if text.is_empty() && self.is_scanning() {
+                text.push_str(scanning_icon);
+            }
+        }
+        if let Some(idle_format) = &args.idle_format {
+            if text.is_empty() && self.runners.iter().all(Runner::is_idle) {
+                let ago = self
+                    .runners
+                    .iter()
+                    .filter_map(|runner| runner.last_synced)
+                    .max()
+                    .map(|at| format_ago(at, self.clock.now_system()));
+                idle_format.render_into(&mut text, |field| match field {
+                    Field::Icon => args.icon.clone(),
+                    Field::Ago => ago.clone().unwrap_or_default(),
+                    _ => String::new(),
+                });
+                text.truncate(text.trim_end().len());
+                text.drain(..text.len() - text.trim_start().len());
+            }
+        }
+        if let Some(idle_icon) = &args.idle_icon {
+            if text.is_empty() && self.runners.iter().all(Runner::is_idle) {
+                text.push_str(idle_icon);
+            }
+        }
+
+        match args.output_format {
+            OutputFormat::Waybar | OutputFormat::Plain => self.write_status(&Status {
+                debug: args.raw.then(|| {
+                    self.runners
+                        .iter()
+                        .flat_map(Runner::pending_json)
+                        .collect::<serde_json::Map<_, _>>()
+                        .into()
+                }),
+                percentage: aggregate
+                    .map(|aggregate| aggregate.completion.rounded(self.args.percent_round) as u64),
+                text: &text,
+                tooltip: &tooltip,
+                class: Some(self.tiered_class(aggregate.as_ref())),
+            }),
+            OutputFormat::JsonFull => self.write_status(&FullStatus {
+                text: &text,
+                tooltip: &tooltip,
+                class: self.tiered_class(aggregate.as_ref()),
+                pending: self
+                    .runners
+                    .iter()
+                    .zip(&displayed)
+                    .flat_map(|(runner, entries)| runner.full_status_entries(entries))
+                    .collect(),
+                aggregate: aggregate.map(|aggregate| FullStatusAggregate {
+                    completion: aggregate.completion.0,
+                    need_bytes: aggregate.need_bytes.map(|bytes| bytes.0),
+                    global_bytes: aggregate.global_bytes.0,
+                }),
+            }),
+        }
+
+        self.text_buffer = text;
+        self.tooltip_buffer = tooltip;
+        self.instance_text_buffer = instance_text;
+        self.instance_tooltip_buffer = instance_tooltip;
+    }
+
+    /// Shows `--disconnected-text` while Syncthing cannot be reached, or else
+    /// the last known progress under the failure class. The regular status
+    /// replaces it once a cycle succeeds again.
+    fn print_failure(&mut self, err: &anyhow::Error) {
+        // While another instance is reachable its progress is still worth showing.
+        let all_failed = self.runners.iter().all(|runner| runner.failure.is_some());
+        let Some(text) = self.args.disconnected_text.as_ref().filter(|_| all_failed) else {
+            self.print_status();
+            return;
+        };
+
+        self.write_status(&Status {
+            debug: None,
+            percentage: None,
+            text,
+            tooltip: &escape_markup(&self.args, &format!("{:#}", err)),
+            class: Some(self.status_class()),
+        });
+    }
+
+    /// The class of whichever instance needs the most attention.
+    fn status_class(&self) -> &'static str {
+        const BY_URGENCY: &[&str] = &[
+            "disconnected",
+            "error",
+            "attention",
+            "syncing",
+            "scanning",
+            "idle",
+        ];
+        self.runners
+            .iter()
+            .map(Runner::status_class)
+            .min_by_key(|class| BY_URGENCY.iter().position(|c| c == class))
+            .unwrap_or("idle")
+    }
+
+    /// With `--class-thresholds`, how far along syncing is, so that a
+    /// stylesheet can shade the module as it progresses.
+    fn tiered_class(&self, aggregate: Option<&Aggregate>) -> &'static str {
+        let class = self.status_class();
+        let (Some((low, high)), Some(aggregate)) = (self.args.class_thresholds, aggregate) else {
+            return class;
+        };
+        if class != "syncing" {
+            return class;
+        }
+
+        let percent = aggregate.completion.rounded(self.args.percent_round);
+        if percent < low {
+            "syncing-low"
+        } else if percent <= high {
+            "syncing-mid"
+        } else {
+            "syncing-high"
+        }
+    }
+
+    fn is_scanning(&self) -> bool {
+        self.runners.iter().any(Runner::is_scanning)
+            && self
+                .runners
+                .iter()
+                .all(|runner| runner.is_scanning() || runner.is_idle())
+    }
+
+    /// The status that clears the bar, printed when the module is stopped.
+    pub fn final_status(&self) -> String {
+        let result = match self.args.output_format {
+            OutputFormat::Plain => Ok(String::new()),
+            OutputFormat::Waybar => serde_json::to_string(&Status {
+                debug: None,
+                percentage: None,
+                text: "",
+                tooltip: "",
+                class: Some("idle"),
+            }),
+            OutputFormat::JsonFull => serde_json::to_string(&FullStatus {
+                text: "",
+                tooltip: "",
+                class: "idle",
+                pending: Vec::new(),
+                aggregate: None,
+            }),
+        };
+        result.unwrap_or_default()
+    }
+
+    /// With `--print-on-signal` the status is only kept for the stdin reader
+    /// to print on request, otherwise it goes straight to stdout.
+    fn write_status(&self, status: &(impl Serialize + StatusText)) {
+        let line = match self.args.output_format {
+            OutputFormat::Plain => status.text().to_string(),
+            OutputFormat::Waybar | OutputFormat::JsonFull => {
+                serde_json::to_string(status).unwrap_or_default()
+            }
+        };
+        if self.args.print_on_signal {
+            *self.latest_status.lock().unwrap() = Some(line);
+            return;
+        }
+        let mut last_line = self.last_line.borrow_mut();
+        if last_line.as_ref() == Some(&line) {
+            return;
+        }
+
+        // Waybar reads from a pipe, so make sure every status leaves the
+        // process right away instead of depending on stdout's buffering.
+        let mut stdout = io::stdout().lock();
+        let _ = writeln!(stdout, "{}", line);
+        let _ = stdout.flush();
+        *last_line = Some(line);
+    }
+
+    /// Prints the latest status whenever a line is read on stdin.
+    fn spawn_print_trigger(&self) {
+        let latest_status = self.latest_status.clone();
+        thread::spawn(move || {
+            for _ in io::stdin().lock().lines().map_while(Result::ok) {
+                // An empty line is still printed, as that clears the bar.
+                if let Some(line) = latest_status.lock().unwrap().as_ref() {
+                    let mut stdout = io::stdout().lock();
+                    let _ = writeln!(stdout, "{}", line);
+                    let _ = stdout.flush();
+                }
+            }
+        });
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct Status<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    debug: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    percentage: Option<u64>,
+    text: &'a str,
+    tooltip: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    class: Option<&'a str>,
+}
+
+/// What `--output-format plain` prints of a status.
+trait StatusText {
+    fn text(&self) -> &str;
+}
+
+impl StatusText for Status<'_> {
+    fn text(&self) -> &str {
+        self.text
+    }
+}
+
+impl StatusText for FullStatus<'_> {
+    fn text(&self) -> &str {
+        self.text
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct FullStatus<'a> {
+    text: &'a str,
+    tooltip: &'a str,
+    class: &'a str,
+    pending: Vec<FullStatusEntry<'a>>,
+    aggregate: Option<FullStatusAggregate>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct FullStatusEntry<'a> {
+    device: &'a str,
+    device_name: &'a str,
+    folder: &'a str,
+    folder_name: &'a str,
+    completion: f64,
+    need_bytes: Option<u64>,
+    global_bytes: u64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct FullStatusAggregate {
+    completion: f64,
+    need_bytes: Option<u64>,
+    global_bytes: u64,
+}
+
+type PendingEntry<'a> = (&'a DeviceID, &'a FolderID, &'a FolderProgress);
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PersistedState {
+    since: u64,
+    start_time: Option<String>,
+    #[serde(default)]
+    last_synced: Option<u64>,
+    pending: HashMap<DeviceID, HashMap<FolderID, PersistedProgress>>,
+}
 
-#[derive(Debug)]
-pub struct Runner {
-    client: ApiClient,
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CachedNames {
     devices: HashMap<DeviceID, DeviceName>,
     folders: HashMap<FolderID, FolderName>,
-    pending: HashMap<DeviceID, HashMap<FolderID, (ProgressPct, NeedBytes)>>,
-    since: u64,
+    folder_devices: HashMap<FolderID, Vec<DeviceID>>,
+    paused_devices: HashSet<DeviceID>,
 }
 
-impl Runner {
-    pub fn new(client: ApiClient) -> Self {
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PersistedProgress {
+    completion: f64,
+    need_bytes: Option<u64>,
+    global_bytes: u64,
+    need_items: u64,
+}
+
+impl From<&FolderProgress> for PersistedProgress {
+    fn from(progress: &FolderProgress) -> Self {
         Self {
-            client,
-            devices: HashMap::new(),
-            folders: HashMap::new(),
-            pending: HashMap::new(),
-            since: 0,
+            completion: progress.completion.0,
+            need_bytes: progress.need_bytes.map(|bytes| bytes.0),
+            global_bytes: progress.global_bytes.0,
+            need_items: progress.need_items,
         }
     }
+}
 
-    pub fn main_loop(&mut self) -> Result<()> {
-        loop {
-            self.get_events()?;
-            self.print_status();
+impl PersistedProgress {
+    /// Counts as updated when loaded, for `--pending-ttl`.
+    fn restore(self, now: Instant) -> FolderProgress {
+        FolderProgress {
+            completion: ProgressPct::clamped(self.completion),
+            need_bytes: self.need_bytes.map(Bytes),
+            global_bytes: Bytes(self.global_bytes),
+            need_items: self.need_items,
+            updated_at: now,
         }
     }
+}
 
-    fn get_events(&mut self) -> Result<()> {
-        let response = self
-            .client
-            .get(&format!(
-                "rest/events?since={}&events=FolderCompletion,DeviceDisconnected",
-                self.since
-            ))?
-            .json::<EventsResponse>()?;
+#[derive(Debug)]
+struct DiskActivity {
+    incoming: bool,
+    path: String,
+    folder: FolderID,
+}
 
-        let need_device_refresh = response
-            .iter()
-            .filter_map(|entry| match &entry.data {
-                EventsResponseData::FolderCompletion { device, .. } => Some(device),
-                _ => None,
-            })
-            .any(|item| !self.devices.contains_key(item));
+#[derive(Debug, Clone, Copy)]
+struct FolderProgress {
+    completion: ProgressPct,
+    /// Unknown for events from before Syncthing v0.14.
+    need_bytes: Option<Bytes>,
+    global_bytes: Bytes,
+    need_items: u64,
+    updated_at: Instant,
+}
 
-        let need_folder_refresh = response
-            .iter()
-            .filter_map(|entry| match &entry.data {
-                EventsResponseData::FolderCompletion { folder, .. } => Some(folder),
-                _ => None,
-            })
-            .any(|item| !self.folders.contains_key(item));
+impl FolderProgress {
+    /// During index exchange Syncthing can briefly report more bytes needed
+    /// than the folder holds, so keep the figures within sane bounds.
+    fn new(
+        completion: ProgressPct,
+        need_bytes: Option<Bytes>,
+        global_bytes: Bytes,
+        items: ItemCounts,
+        updated_at: Instant,
+    ) -> Self {
+        let need_bytes = need_bytes.map(|need_bytes| {
+            if global_bytes.0 > 0 {
+                Bytes(need_bytes.0.min(global_bytes.0))
+            } else {
+                need_bytes
+            }
+        });
 
-        if need_device_refresh || need_folder_refresh {
-            self.refresh_devices_and_folders()?;
+        Self {
+            completion: ProgressPct::clamped(completion.0),
+            need_bytes,
+            global_bytes,
+            need_items: items.need_items + items.need_deletes,
+            updated_at,
         }
+    }
+}
 
-        response.iter().for_each(|entry| match &entry.data {
-            EventsResponseData::FolderCompletion {
-                device,
-                folder,
-                completion,
-                ..
-            } if *completion == ProgressPct(100.) => {
-                self.pending.entry(device.clone()).and_modify(|v| {
-                    v.remove(folder);
-                });
-            }
-            EventsResponseData::FolderCompletion {
-                device,
-                folder,
-                completion,
-                need_bytes,
-            } => {
-                self.pending
-                    .entry(device.clone())
-                    .or_default()
-                    .insert(folder.clone(), (*completion, *need_bytes));
-            }
+#[derive(Debug)]
+struct LocalFolder {
+    progress: FolderProgress,
+    state: String,
+}
 
-            EventsResponseData::DeviceDisconnected { id } => {
-                self.pending.remove(id);
-            }
-        });
+impl LocalFolder {
+    fn is_busy(&self) -> bool {
+        self.is_scanning() || self.state.starts_with("sync")
+    }
+
+    fn is_scanning(&self) -> bool {
+        self.state.starts_with("scan")
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Aggregate {
+    completion: ProgressPct,
+    need_bytes: Option<Bytes>,
+    global_bytes: Bytes,
+    need_items: u64,
+}
 
-        self.since = response.last().map(|entry| entry.id).unwrap_or(self.since);
+impl Aggregate {
+    /// Combined progress, weighted by folder size when Syncthing reported it
+    /// and averaged per folder otherwise or with `--aggregate mean`.
+    fn of(progresses: &[&FolderProgress], mode: AggregateMode) -> Option<Self> {
+        match progresses {
+            [] => return None,
+            // Syncthing's own figure also counts items, such as deletions.
+            [progress] => return Some(Self::from(*progress)),
+            _ => {}
+        }
 
-        self.refresh_connected_devices()?;
+        let need_bytes = sum_known(progresses.iter().map(|p| p.need_bytes));
+        let global_bytes = progresses.iter().map(|p| p.global_bytes.0).sum::<u64>();
 
-        Ok(())
+        let completion = if global_bytes > 0 && mode == AggregateMode::Weighted {
+            let done = global_bytes.saturating_sub(need_bytes.unwrap_or_default().0);
+            100. * done as f64 / global_bytes as f64
+        } else {
+            progresses.iter().map(|p| p.completion.0).sum::<f64>() / progresses.len() as f64
+        };
+
+        Some(Self {
+            completion: ProgressPct::clamped(completion),
+            need_bytes,
+            global_bytes: Bytes(global_bytes),
+            need_items: progresses.iter().map(|p| p.need_items).sum(),
+        })
     }
+}
 
-    fn refresh_connected_devices(&mut self) -> Result<()> {
-        let response = self
-            .client
-            .get("rest/system/connections")?
-            .json::<SystemConnectionsResponse>()?;
+impl From<&FolderProgress> for Aggregate {
+    fn from(progress: &FolderProgress) -> Self {
+        Self {
+            completion: progress.completion,
+            need_bytes: progress.need_bytes,
+            global_bytes: progress.global_bytes,
+            need_items: progress.need_items,
+        }
+    }
+}
 
-        response
-            .connections
-            .iter()
-            .filter(|(_, v)| !v.connected)
-            .for_each(|(id, _)| {
-                self.pending.remove(id);
-            });
+/// Transfer rate of a device, from the change in its byte counters between two
+/// reads of `rest/system/connections`.
+#[derive(Debug)]
+struct TransferRate {
+    bytes_total: u64,
+    sampled_at: Instant,
+    bytes_per_sec: Option<f64>,
+}
 
-        Ok(())
+impl TransferRate {
+    fn sample(&mut self, bytes_total: u64, now: Instant) {
+        let elapsed = now.duration_since(self.sampled_at).as_secs_f64();
+        // The counters start over when the device reconnects.
+        self.bytes_per_sec = (elapsed > 0. && bytes_total >= self.bytes_total)
+            .then(|| (bytes_total - self.bytes_total) as f64 / elapsed);
+        self.bytes_total = bytes_total;
+        self.sampled_at = now;
     }
+}
 
-    fn refresh_devices_and_folders(&mut self) -> Result<()> {
-        log::debug!("Refreshing devices...");
+#[derive(Debug)]
+struct FolderCluster {
+    behind: usize,
+    min_completion: ProgressPct,
+    need_bytes: Option<Bytes>,
+}
 
-        let response = self
-            .client
-            .get("rest/system/config")?
-            .json::<SystemConfigResponse>()?;
+fn is_transient(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<reqwest::Error>().is_some_and(|err| {
+        err.is_connect()
+            || err.is_timeout()
+            || err.is_request()
+            || err.is_body()
+            || err.status().is_some_and(|status| status.is_server_error())
+    }) || err
+        .downcast_ref::<unix_socket::Error>()
+        .is_some_and(unix_socket::Error::is_transient)
+}
 
-        self.devices = response
-            .devices
-            .into_iter()
-            .map(|entry| (entry.device_id, entry.name))
-            .collect();
+fn is_timeout(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<reqwest::Error>()
+        .is_some_and(reqwest::Error::is_timeout)
+        || err
+            .downcast_ref::<unix_socket::Error>()
+            .is_some_and(unix_socket::Error::is_timeout)
+}
 
-        self.folders = response
-            .folders
-            .into_iter()
-            .map(|entry| (entry.id, entry.label))
-            .collect();
+/// `$XDG_CACHE_HOME/waybar-syncthing/<name>-<key>.json`, or under `~/.cache`
+/// when that is not set.
+fn cache_path(name: &str, key: &str) -> Option<PathBuf> {
+    let base = env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+    Some(
+        base.join("waybar-syncthing")
+            .join(format!("{}-{}.json", name, key)),
+    )
+}
 
-        Ok(())
+/// A cache file as written by `write_cache_json`, or `None` when there is none
+/// or it cannot be understood.
+fn read_cache_json<T: DeserializeOwned>(name: &str, key: &str) -> Option<T> {
+    let path = cache_path(name, key)?;
+    match fs::read(&path).map(|json| serde_json::from_slice(&json)) {
+        Ok(Ok(value)) => Some(value),
+        Ok(Err(err)) => {
+            log::warn!("Ignoring unreadable cache file {}: {}", path.display(), err);
+            None
+        }
+        Err(err) => {
+            log::debug!("Nothing loaded from {}: {}", path.display(), err);
+            None
+        }
     }
+}
 
-    fn print_status(&self) {
-        let text = self
-            .pending
-            .iter()
-            .flat_map(|(_, folders)| {
-                folders
-                    .iter()
-                    .map(|(_, (completion, need_bytes))| {
-                        format!(" {}%/{}", completion, need_bytes)
-                    })
-                    .collect::<Vec<_>>()
-            })
-            .collect::<Vec<_>>()
-            .join(" | ");
+fn write_cache_json<T: Serialize>(name: &str, key: &str, value: &T) {
+    let Some(path) = cache_path(name, key) else {
+        return;
+    };
 
-        let tooltip = self
-            .pending
-            .iter()
-            .flat_map(|(device, folders)| {
-                let device_name = self
-                    .devices
-                    .get(device)
-                    .map(|v| v.as_str())
-                    .unwrap_or(device.as_str());
-                folders
-                    .iter()
-                    .map(|(folder, (completion, need_bytes))| {
-                        let folder_name = self
-                            .folders
-                            .get(folder)
-                            .map(|v| v.as_str())
-                            .unwrap_or(folder.as_str());
-
-                        format!(
-                            "{:<10} {:<10} ({:.0}%, {})",
-                            format!("{}:", device_name),
-                            folder_name,
-                            completion,
-                            need_bytes
-                        )
-                    })
-                    .collect::<Vec<_>>()
-            })
-            .collect::<Vec<_>>()
-            .join("\n");
-
-        println!(
-            "{}",
-            serde_json::json!({
-                "text": text,
-                "tooltip": tooltip
-            })
+    // Written next to the file and renamed, so a crash never leaves half of it.
+    let temp = path.with_extension("json.tmp");
+    let result = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| fs::write(&temp, serde_json::to_vec(value)?))
+        .and_then(|()| fs::rename(&temp, &path));
+    if let Err(err) = result {
+        log::warn!("Failed to write {}: {}", path.display(), err);
+    }
+}
+
+/// 64-bit FNV-1a, which unlike `DefaultHasher` stays the same across Rust
+/// releases and so keeps finding the cache files.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// A response that could not be understood, which another cycle may get past.
+fn is_malformed(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<reqwest::Error>()
+        .is_some_and(|err| err.is_decode())
+        || matches!(
+            err.downcast_ref::<unix_socket::Error>(),
+            Some(unix_socket::Error::Decode(_))
+        )
+}
+
+/// A 4xx other than the 401 and 403 that `Unauthorized` stands for.
+fn is_client_error(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<reqwest::Error>()
+        .and_then(reqwest::Error::status)
+        .is_some_and(|status| status.is_client_error())
+        || matches!(
+            err.downcast_ref::<unix_socket::Error>(),
+            Some(unix_socket::Error::Status(status)) if status.is_client_error()
+        )
+}
+
+/// Syncthing answering with an error is told apart from not answering at all.
+fn failure_class(err: &anyhow::Error) -> &'static str {
+    let answered = err
+        .downcast_ref::<reqwest::Error>()
+        .is_some_and(|err| err.is_status())
+        || matches!(
+            err.downcast_ref::<unix_socket::Error>(),
+            Some(unix_socket::Error::Status(_))
         );
+    if answered {
+        "error"
+    } else {
+        "disconnected"
+    }
+}
+
+/// With no bytes left only metadata (deletes, renames, permissions) remains,
+/// which `0 MiB` would make look like a sync that is stuck.
+/// Nothing is shown for counts that Syncthing did not report.
+fn format_need(
+    need_bytes: Option<Bytes>,
+    need_items: u64,
+    format: ByteFormat,
+    si: bool,
+) -> Option<String> {
+    match need_bytes? {
+        Bytes(0) if need_items > 0 => Some("metadata".to_string()),
+        need_bytes => Some(need_bytes.format(format, si)),
+    }
+}
+
+/// The total of the byte counts that are known, if any is.
+fn sum_known(bytes: impl IntoIterator<Item = Option<Bytes>>) -> Option<Bytes> {
+    bytes
+        .into_iter()
+        .flatten()
+        .map(|bytes| bytes.0)
+        .reduce(|total, bytes| total + bytes)
+        .map(Bytes)
+}
+
+fn format_eta(eta: Duration) -> String {
+    let secs = eta.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {}s", m, s),
+        (h, m, _) => format!("{}h {}m", h, m),
+    }
+}
+
+/// The coarsest unit that fits, as in `3m ago`.
+fn format_ago(at: SystemTime, now: SystemTime) -> String {
+    let secs = now
+        .duration_since(at)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    match (secs / 86_400, secs / 3600, secs / 60) {
+        (0, 0, 0) => "just now".to_string(),
+        (0, 0, m) => format!("{}m ago", m),
+        (0, h, _) => format!("{}h ago", h),
+        (d, _, _) => format!("{}d ago", d),
+    }
+}
+
+/// Names and messages go into Pango markup unless `--no-markup` is given.
+fn escape_markup<'a>(args: &Args, text: &'a str) -> Cow<'a, str> {
+    if args.no_markup || !text.contains(['&', '<', '>', '\'', '"']) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '\'' => escaped.push_str("&#39;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+fn push_separator(buffer: &mut String, separator: &str) {
+    if !buffer.is_empty() {
+        buffer.push_str(separator);
+    }
+}
+
+fn pad(buffer: &mut String, width: usize, min_width: usize) {
+    buffer.extend(std::iter::repeat_n(' ', min_width.saturating_sub(width)));
+}
+
+/// Keeps the first `max_segments` of the `|`-separated text, which come in
+/// the order of the tooltip, and counts the rest.
+fn limit_segments(text: &mut String, max_segments: usize) {
+    let segments = text.split(" | ").count();
+    if text.is_empty() || segments <= max_segments {
+        return;
+    }
+
+    let cut = match max_segments {
+        0 => 0,
+        _ => text
+            .match_indices(" | ")
+            .nth(max_segments - 1)
+            .map_or(text.len(), |(index, _)| index),
+    };
+    text.truncate(cut);
+    push_separator(text, " | ");
+    let _ = write!(text, "+{} more", segments - max_segments);
+}
+
+fn truncate_to_width(text: &mut String, max_width: usize) {
+    if text.width() <= max_width {
+        return;
+    }
+
+    const ELLIPSIS: char = '…';
+    let budget = max_width.saturating_sub(ELLIPSIS.width().unwrap_or(1));
+
+    let mut width = 0;
+    let cut = text
+        .char_indices()
+        .find(|(_, c)| {
+            width += c.width().unwrap_or(0);
+            width > budget
+        })
+        .map_or(text.len(), |(index, _)| index);
+
+    text.truncate(cut);
+    if max_width > 0 {
+        text.push(ELLIPSIS);
     }
 }
 
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 struct DeviceID(String);
 
 impl DeviceID {
     pub fn as_str(&self) -> &str {
         &self.0
     }
+
+    /// The first group of the ID, which Syncthing itself uses as a short ID.
+    pub fn short(&self) -> &str {
+        self.0.split('-').next().unwrap_or(&self.0)
+    }
 }
 
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 struct DeviceName(String);
 
 impl DeviceName {
@@ -211,41 +3225,82 @@ impl DeviceName {
 #[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
 struct ProgressPct(f64);
 
-impl fmt::Display for ProgressPct {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.0.floor())
+impl ProgressPct {
+    fn clamped(value: f64) -> Self {
+        Self(value.clamp(0., 100.))
     }
 }
 
-#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
-struct NeedBytes(u64);
+impl ProgressPct {
+    /// The whole percentage to show.
+    fn rounded(self, mode: PercentRound) -> f64 {
+        match mode {
+            PercentRound::Floor => self.0.floor(),
+            PercentRound::Round => self.0.round(),
+            PercentRound::Ceil => self.0.ceil(),
+        }
+    }
+}
 
-impl fmt::Display for NeedBytes {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        const BYTES_IN_MIB: u64 = 1024 * 1024;
-        const BYTES_IN_GIB: u64 = 1024 * 1024 * 1024;
+#[derive(Deserialize, Debug)]
+struct SystemStatusResponse {
+    #[serde(rename = "startTime")]
+    start_time: String,
+    #[serde(rename = "myID", default)]
+    my_id: Option<DeviceID>,
+}
 
-        let format_number = |value: f64| {
-            if value.fract() == 0.0 {
-                format!("{:.0}", value)
-            } else {
-                format!("{:.2}", value)
+#[derive(Deserialize, Debug)]
+struct DbCompletionResponse {
+    completion: ProgressPct,
+    #[serde(rename = "needBytes")]
+    need_bytes: Bytes,
+    #[serde(rename = "globalBytes", default)]
+    global_bytes: Bytes,
+    #[serde(flatten)]
+    items: ItemCounts,
+}
+
+/// Newer APIs may wrap the completion figures in a `completion` object.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum DbCompletionBody {
+    Nested { completion: DbCompletionResponse },
+    Flat(DbCompletionResponse),
+}
+
+impl From<DbCompletionBody> for DbCompletionResponse {
+    fn from(body: DbCompletionBody) -> Self {
+        match body {
+            DbCompletionBody::Nested { completion } | DbCompletionBody::Flat(completion) => {
+                completion
             }
-        };
+        }
+    }
+}
 
-        if self.0 >= BYTES_IN_GIB {
-            write!(
-                f,
-                "{} GiB",
-                format_number(self.0 as f64 / BYTES_IN_GIB as f64)
-            )
-        } else {
-            write!(
-                f,
-                "{} MiB",
-                format_number(self.0 as f64 / BYTES_IN_MIB as f64)
-            )
+/// Item counts that come with completion data, bytes alone do not account for
+/// deletes or empty files still to be synced.
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+struct ItemCounts {
+    #[serde(rename = "globalItems", default)]
+    global_items: u64,
+    #[serde(rename = "needItems", default)]
+    need_items: u64,
+    #[serde(rename = "needDeletes", default)]
+    need_deletes: u64,
+}
+
+impl ItemCounts {
+    fn completion(&self, reported: ProgressPct, mode: CompletionMode) -> ProgressPct {
+        let total = self.global_items + self.need_deletes;
+        if mode == CompletionMode::Sync || total == 0 {
+            return reported;
         }
+
+        let need = (self.need_items + self.need_deletes).min(total);
+        let items = 100. * (1. - need as f64 / total as f64);
+        ProgressPct(reported.0.min(items))
     }
 }
 
@@ -254,14 +3309,55 @@ struct SystemConnectionsResponse {
     connections: HashMap<DeviceID, SystemConnectionsResponseDevice>,
 }
 
+/// Syncthing reports one entry per device, but devices with several
+/// connections (multipath) may be reported as a list of them instead.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum SystemConnectionsResponseDevice {
+    Single(SystemConnectionsResponseConnection),
+    Multiple(Vec<SystemConnectionsResponseConnection>),
+}
+
+impl SystemConnectionsResponseDevice {
+    fn is_connected(&self) -> bool {
+        self.connections().iter().any(|c| c.connected)
+    }
+
+    /// Bytes sent and received over every connection to the device.
+    fn bytes_total(&self) -> u64 {
+        self.connections()
+            .iter()
+            .map(|c| c.in_bytes_total + c.out_bytes_total)
+            .sum()
+    }
+
+    fn connections(&self) -> &[SystemConnectionsResponseConnection] {
+        match self {
+            Self::Single(connection) => std::slice::from_ref(connection),
+            Self::Multiple(connections) => connections,
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
-struct SystemConnectionsResponseDevice {
+struct SystemConnectionsResponseConnection {
     connected: bool,
+    #[serde(rename = "inBytesTotal", default)]
+    in_bytes_total: u64,
+    #[serde(rename = "outBytesTotal", default)]
+    out_bytes_total: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct DbStatusResponse {
+    state: String,
 }
 
 #[derive(Deserialize, Debug)]
 struct SystemConfigResponse {
+    #[serde(default)]
     devices: Vec<SystemConfigResponseDevice>,
+    #[serde(default)]
     folders: Vec<SystemConfigResponseFolder>,
 }
 
@@ -270,18 +3366,22 @@ struct SystemConfigResponseDevice {
     #[serde(rename = "deviceID")]
     device_id: DeviceID,
     name: DeviceName,
+    #[serde(default)]
+    paused: bool,
 }
 
 #[derive(Deserialize, Debug)]
 struct SystemConfigResponseFolder {
     id: FolderID,
     label: FolderName,
+    #[serde(default)]
+    devices: Vec<SystemConfigResponseFolderDevice>,
 }
 
 #[derive(Deserialize, Debug)]
-enum EventsResponseType {
-    FolderCompletion,
-    DeviceDisconnected,
+struct SystemConfigResponseFolderDevice {
+    #[serde(rename = "deviceID")]
+    device_id: DeviceID,
 }
 
 type EventsResponse = Vec<EventsResponseEntry>;
@@ -293,7 +3393,29 @@ struct EventsResponseEntry {
     data: EventsResponseData,
 }
 
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+type DiskEventsResponse = Vec<DiskEventsResponseEntry>;
+
+#[derive(Deserialize, Debug)]
+struct DiskEventsResponseEntry {
+    id: u64,
+    #[serde(rename = "type")]
+    event_type: DiskEventType,
+    data: DiskEventsResponseData,
+}
+
+#[derive(Deserialize, Debug)]
+enum DiskEventType {
+    LocalChangeDetected,
+    RemoteChangeDetected,
+}
+
+#[derive(Deserialize, Debug)]
+struct DiskEventsResponseData {
+    folder: FolderID,
+    path: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 struct FolderID(String);
 
 impl FolderID {
@@ -302,7 +3424,7 @@ impl FolderID {
     }
 }
 
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 struct FolderName(String);
 
 impl FolderName {
@@ -314,14 +3436,108 @@ impl FolderName {
 #[derive(Deserialize, Debug)]
 #[serde(tag = "type", content = "data")]
 enum EventsResponseData {
+    ConfigSaved {},
+    DeviceConnected {
+        id: DeviceID,
+    },
     DeviceDisconnected {
         id: DeviceID,
     },
+    FolderPaused {
+        id: FolderID,
+    },
+    /// Before v0.14 Syncthing sent no byte or item counts.
     FolderCompletion {
         completion: ProgressPct,
         #[serde(rename = "needBytes")]
-        need_bytes: NeedBytes,
+        need_bytes: Option<Bytes>,
+        #[serde(rename = "globalBytes", default)]
+        global_bytes: Bytes,
+        #[serde(flatten)]
+        items: ItemCounts,
+        device: DeviceID,
+        folder: FolderID,
+    },
+    FolderErrors {
+        folder: FolderID,
+        #[serde(default)]
+        errors: Vec<FolderError>,
+    },
+    FolderSummary {
+        folder: FolderID,
+        summary: FolderSummary,
+    },
+    StateChanged {
+        folder: FolderID,
+        to: String,
+    },
+    DevicePaused {
+        device: DeviceID,
+    },
+    DeviceResumed {
+        device: DeviceID,
+    },
+    DeviceRejected {
+        device: DeviceID,
+        #[serde(default)]
+        name: String,
+    },
+    FolderRejected {
+        device: DeviceID,
+        folder: FolderID,
+        #[serde(rename = "folderLabel", default)]
+        folder_label: String,
+    },
+    PendingDevicesChanged {
+        #[serde(default)]
+        added: Vec<PendingDevice>,
+        #[serde(default)]
+        removed: Vec<PendingDevice>,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+struct PendingDevice {
+    #[serde(rename = "deviceID")]
+    device: DeviceID,
+    #[serde(default)]
+    name: String,
+}
+
+/// A device or folder someone offered that waits to be accepted in Syncthing.
+#[derive(Debug, PartialEq, Eq)]
+enum Approval {
+    Device {
+        device: DeviceID,
+        name: String,
+    },
+    Folder {
         device: DeviceID,
         folder: FolderID,
+        label: String,
     },
 }
+
+#[derive(Deserialize, Debug, Clone)]
+struct FolderError {
+    error: String,
+    path: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct FolderSummary {
+    #[serde(rename = "globalBytes")]
+    global_bytes: Bytes,
+    #[serde(rename = "needBytes")]
+    need_bytes: Bytes,
+    #[serde(rename = "needTotalItems", default)]
+    need_items: u64,
+    state: String,
+}
+
+fn encode(value: &str) -> String {
+    byte_serialize(value.as_bytes()).collect()
+}
+
+#[cfg(test)]
+mod tests;
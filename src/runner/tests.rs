@@ -0,0 +1,483 @@
+use super::*;
+use crate::api_client::Response;
+use clap::Parser;
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+
+const LAPTOP: &str = "LAPTOP-ID";
+const PHONE: &str = "PHONE-ID";
+const PHOTOS: &str = "photos-id";
+const MIB: u64 = 1024 * 1024;
+
+/// Plays back canned responses per path like the stub server of the
+/// integration tests, without a socket in between.
+#[derive(Debug, Clone, Default)]
+struct FakeApi {
+    responses: Arc<Mutex<HashMap<String, VecDeque<Value>>>>,
+    requests: Arc<Mutex<Vec<String>>>,
+    failing: Arc<Mutex<Vec<String>>>,
+}
+
+impl FakeApi {
+    /// Queues `body` for `path`. The last response of a path repeats, except
+    /// for `rest/events`, which has nothing new once its queue is empty.
+    fn respond(self, path: &str, body: Value) -> Self {
+        self.responses
+            .lock()
+            .unwrap()
+            .entry(path.to_string())
+            .or_default()
+            .push_back(body);
+        self
+    }
+
+    /// Makes every request to `path` fail as if Syncthing was down.
+    fn fail(self, path: &str) -> Self {
+        self.failing.lock().unwrap().push(path.to_string());
+        self
+    }
+
+    fn requests_to(&self, path: &str) -> usize {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|request| request.split('?').next() == Some(path))
+            .count()
+    }
+}
+
+impl SyncthingApi for FakeApi {
+    fn get(&self, path: &str) -> Result<Response> {
+        self.requests.lock().unwrap().push(path.to_string());
+        let route = path.split('?').next().unwrap_or(path);
+        if self
+            .failing
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|failing| failing == route)
+        {
+            let refused = io::Error::from(io::ErrorKind::ConnectionRefused);
+            return Err(unix_socket::Error::Io(refused).into());
+        }
+        let mut responses = self.responses.lock().unwrap();
+        let queue = responses
+            .get_mut(route)
+            .with_context(|| format!("no response for {}", route))?;
+        let body = if queue.len() > 1 || route == "rest/events" {
+            queue.pop_front().unwrap_or_else(|| json!([]))
+        } else {
+            queue[0].clone()
+        };
+
+        Ok(Response::Unix(unix_socket::Response {
+            status: StatusCode::OK,
+            content_type: Some("application/json".to_string()),
+            body: serde_json::to_vec(&body)?,
+        }))
+    }
+
+    fn long_poll(&self, path: &str, _wait: Duration) -> Result<Response> {
+        self.get(path)
+    }
+
+    fn reconnect(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn base_url(&self) -> &str {
+        "http://fake"
+    }
+}
+
+/// Time that only moves when told to, or when slept through.
+#[derive(Debug)]
+struct FakeClock {
+    start: Instant,
+    start_system: SystemTime,
+    elapsed: Mutex<Duration>,
+}
+
+impl FakeClock {
+    fn new() -> Arc<Self> {
+        Arc::new(Self {
+            start: Instant::now(),
+            start_system: SystemTime::now(),
+            elapsed: Mutex::default(),
+        })
+    }
+
+    fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        self.start + *self.elapsed.lock().unwrap()
+    }
+
+    fn now_system(&self) -> SystemTime {
+        self.start_system + *self.elapsed.lock().unwrap()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+fn syncthing() -> FakeApi {
+    FakeApi::default()
+        .respond(
+            "rest/system/status",
+            json!({ "myID": "LOCAL-ID", "startTime": "2024-01-01T00:00:00Z" }),
+        )
+        .respond("rest/system/connections", json!({ "connections": {} }))
+}
+
+fn config(devices: &[(&str, &str)]) -> Value {
+    json!({
+        "devices": devices
+            .iter()
+            .map(|(id, name)| json!({ "deviceID": id, "name": name }))
+            .collect::<Vec<_>>(),
+        "folders": [{ "id": PHOTOS, "label": "Photos" }],
+    })
+}
+
+fn folder_completion(id: u64, device: &str, completion: f64, need_bytes: u64) -> Value {
+    json!({
+        "id": id,
+        "type": "FolderCompletion",
+        "data": {
+            "device": device,
+            "folder": PHOTOS,
+            "completion": completion,
+            "needBytes": need_bytes,
+        },
+    })
+}
+
+fn parse_args(args: &[&str]) -> Args {
+    Args::parse_from(
+        ["waybar-syncthing", "--api-key", "fake-api-key"]
+            .iter()
+            .chain(args),
+    )
+}
+
+fn runner(api: &FakeApi, clock: &Arc<FakeClock>, args: &[&str]) -> Runner {
+    Runner::new(
+        Box::new(api.clone()),
+        clock.clone(),
+        parse_args(args),
+        "fake".to_string(),
+    )
+}
+
+/// `--print-on-signal` keeps every status off stdout, in `latest_status`.
+fn runners(apis: &[&FakeApi], clock: &Arc<FakeClock>, args: &[&str]) -> Runners {
+    let args = parse_args(&[args, &["--print-on-signal"]].concat());
+    let runners = apis
+        .iter()
+        .map(|api| {
+            Runner::new(
+                Box::new((*api).clone()),
+                clock.clone(),
+                args.clone(),
+                "fake".to_string(),
+            )
+        })
+        .collect();
+    Runners::with_runners(args, clock.clone(), runners)
+}
+
+fn latest_text(runners: &Runners) -> Value {
+    let status = runners.latest_status.lock().unwrap();
+    serde_json::from_str::<Value>(status.as_deref().unwrap()).unwrap()["text"].clone()
+}
+
+/// Runs one cycle and returns what it would show.
+fn cycle(runner: &mut Runner) -> (String, String) {
+    runner.run_cycle(Duration::from_secs(1)).unwrap();
+    let mut text = String::new();
+    let mut tooltip = String::new();
+    runner.write_instance(&runner.displayed_pending(), &mut text, &mut tooltip);
+    (text, tooltip)
+}
+
+#[test]
+fn folder_at_100_is_no_longer_pending() {
+    let api = syncthing()
+        .respond("rest/system/config", config(&[(LAPTOP, "laptop")]))
+        .respond(
+            "rest/events",
+            json!([folder_completion(1, LAPTOP, 50., MIB)]),
+        )
+        .respond(
+            "rest/events",
+            json!([folder_completion(2, LAPTOP, 100., 0)]),
+        );
+    let mut runner = runner(&api, &FakeClock::new(), &[]);
+
+    assert_eq!(
+        cycle(&mut runner),
+        (
+            "\u{f2f1} 50%/1 MiB".to_string(),
+            "<b>laptop</b>\n  Photos     50% (1 MiB)".to_string()
+        )
+    );
+    assert_eq!(cycle(&mut runner), (String::new(), String::new()));
+    assert!(runner.displayed_pending().is_empty());
+}
+
+#[test]
+fn disconnected_device_is_no_longer_pending() {
+    let api = syncthing()
+        .respond(
+            "rest/system/config",
+            config(&[(LAPTOP, "laptop"), (PHONE, "phone")]),
+        )
+        .respond(
+            "rest/events",
+            json!([
+                folder_completion(1, LAPTOP, 50., MIB),
+                folder_completion(2, PHONE, 40., MIB),
+            ]),
+        )
+        .respond(
+            "rest/events",
+            json!([{ "id": 3, "type": "DeviceDisconnected", "data": { "id": PHONE } }]),
+        );
+    let mut runner = runner(&api, &FakeClock::new(), &[]);
+
+    cycle(&mut runner);
+    let (_, tooltip) = cycle(&mut runner);
+    assert_eq!(tooltip, "<b>laptop</b>\n  Photos     50% (1 MiB)");
+    assert_eq!(runner.displayed_pending().len(), 1);
+}
+
+#[test]
+fn unknown_device_refreshes_the_config() {
+    let api = syncthing()
+        .respond("rest/system/config", config(&[(LAPTOP, "laptop")]))
+        .respond(
+            "rest/system/config",
+            config(&[(LAPTOP, "laptop"), (PHONE, "phone")]),
+        )
+        .respond(
+            "rest/events",
+            json!([folder_completion(1, LAPTOP, 50., MIB)]),
+        )
+        .respond(
+            "rest/events",
+            json!([folder_completion(2, PHONE, 40., MIB)]),
+        );
+    let mut runner = runner(&api, &FakeClock::new(), &[]);
+
+    cycle(&mut runner);
+    assert_eq!(api.requests_to("rest/system/config"), 1);
+    let (_, tooltip) = cycle(&mut runner);
+    assert_eq!(api.requests_to("rest/system/config"), 2);
+    assert_eq!(
+        tooltip,
+        "<b>laptop</b>\n  Photos     50% (1 MiB)\n<b>phone</b>\n  Photos     40% (1 MiB)"
+    );
+}
+
+#[test]
+fn output_interval_coalesces_rapid_updates() {
+    let api = syncthing()
+        .respond("rest/system/config", config(&[(LAPTOP, "laptop")]))
+        .respond(
+            "rest/events",
+            json!([folder_completion(1, LAPTOP, 10., MIB)]),
+        )
+        .respond(
+            "rest/events",
+            json!([folder_completion(2, LAPTOP, 20., MIB)]),
+        )
+        .respond(
+            "rest/events",
+            json!([folder_completion(3, LAPTOP, 30., MIB)]),
+        );
+    let clock = FakeClock::new();
+    let mut runners = runners(&[&api], &clock, &["--output-interval", "1000"]);
+
+    runners.poll_instances().unwrap();
+    assert_eq!(latest_text(&runners), "\u{f2f1} 10%/1 MiB");
+    runners.poll_instances().unwrap();
+    runners.poll_instances().unwrap();
+    assert_eq!(latest_text(&runners), "\u{f2f1} 10%/1 MiB");
+    // The long-poll after a deferred print returns in time for it.
+    assert!(api
+        .requests
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|r| r.starts_with("rest/events?since=2&") && r.ends_with("&timeout=1")));
+
+    clock.advance(Duration::from_secs(1));
+    runners.poll_instances().unwrap();
+    assert_eq!(latest_text(&runners), "\u{f2f1} 30%/1 MiB");
+}
+
+#[test]
+fn stale_pending_entry_expires() {
+    let api = syncthing()
+        .respond(
+            "rest/system/config",
+            config(&[(LAPTOP, "laptop"), (PHONE, "phone")]),
+        )
+        .respond(
+            "rest/events",
+            json!([folder_completion(1, LAPTOP, 10., MIB)]),
+        )
+        .respond(
+            "rest/events",
+            json!([folder_completion(2, PHONE, 20., MIB)]),
+        );
+    let clock = FakeClock::new();
+    let mut runner = runner(&api, &clock, &["--pending-ttl", "1"]);
+
+    cycle(&mut runner);
+    clock.advance(Duration::from_millis(600));
+    cycle(&mut runner);
+    assert_eq!(runner.displayed_pending().len(), 2);
+
+    clock.advance(Duration::from_millis(600));
+    let (_, tooltip) = cycle(&mut runner);
+    assert_eq!(tooltip, "<b>phone</b>\n  Photos     20% (1 MiB)");
+}
+
+#[test]
+fn progress_is_only_ignored_within_the_completion_grace() {
+    let api = syncthing()
+        .respond("rest/system/config", config(&[(LAPTOP, "laptop")]))
+        .respond(
+            "rest/events",
+            json!([folder_completion(1, LAPTOP, 50., MIB)]),
+        )
+        .respond(
+            "rest/events",
+            json!([folder_completion(2, LAPTOP, 100., 0)]),
+        )
+        .respond(
+            "rest/events",
+            json!([folder_completion(3, LAPTOP, 50., MIB)]),
+        )
+        .respond(
+            "rest/events",
+            json!([folder_completion(4, LAPTOP, 60., MIB)]),
+        );
+    let clock = FakeClock::new();
+    let mut runner = runner(&api, &clock, &["--completion-grace", "2000"]);
+
+    cycle(&mut runner);
+    cycle(&mut runner);
+    clock.advance(Duration::from_millis(1900));
+    assert_eq!(cycle(&mut runner), (String::new(), String::new()));
+
+    clock.advance(Duration::from_millis(100));
+    let (text, _) = cycle(&mut runner);
+    assert_eq!(text, "\u{f2f1} 60%/1 MiB");
+}
+
+#[test]
+fn idle_format_tells_how_long_ago_folders_got_in_sync() {
+    let api = syncthing()
+        .respond("rest/system/config", config(&[(LAPTOP, "laptop")]))
+        .respond(
+            "rest/events",
+            json!([folder_completion(1, LAPTOP, 50., MIB)]),
+        )
+        .respond(
+            "rest/events",
+            json!([folder_completion(2, LAPTOP, 100., 0)]),
+        );
+    let clock = FakeClock::new();
+    let mut runners = runners(&[&api], &clock, &["--idle-format", "synced {ago}"]);
+
+    runners.poll_instances().unwrap();
+    runners.poll_instances().unwrap();
+    assert_eq!(latest_text(&runners), "synced just now");
+
+    clock.advance(Duration::from_secs(3 * 60));
+    runners.print_status();
+    assert_eq!(latest_text(&runners), "synced 3m ago");
+
+    clock.advance(Duration::from_secs(2 * 3600));
+    runners.print_status();
+    assert_eq!(latest_text(&runners), "synced 2h ago");
+
+    clock.advance(Duration::from_secs(2 * 86_400));
+    runners.print_status();
+    assert_eq!(latest_text(&runners), "synced 2d ago");
+}
+
+#[test]
+fn hang_timeout_waits_for_every_instance() {
+    let healthy = syncthing()
+        .respond("rest/system/config", config(&[(LAPTOP, "laptop")]))
+        .respond("rest/events", json!([]));
+    let down = FakeApi::default().fail("rest/system/status");
+    let clock = FakeClock::new();
+    let args = ["--hang-timeout", "5"];
+
+    let mut one_up = runners(&[&healthy, &down], &clock, &args);
+    for _ in 0..20 {
+        one_up.poll_instances().unwrap();
+        clock.advance(Duration::from_secs(1));
+    }
+
+    let mut all_down = runners(&[&down, &down], &clock, &args);
+    let started = clock.now();
+    let err = (0..100)
+        .find_map(|_| all_down.poll_instances().err())
+        .unwrap();
+    assert!(err.is::<Hung>());
+    assert!(clock.now().duration_since(started) >= Duration::from_secs(5));
+}
+
+#[test]
+fn truncation_keeps_zero_width_characters_with_their_base() {
+    let mut text = "abc\u{301}def".to_string();
+    truncate_to_width(&mut text, 4);
+    assert_eq!(text, "abc\u{301}\u{2026}");
+
+    let mut text = "ab\u{65e5}\u{301}".to_string();
+    truncate_to_width(&mut text, 3);
+    assert_eq!(text, "ab\u{2026}");
+
+    let mut text = "abcd\u{301}".to_string();
+    truncate_to_width(&mut text, 4);
+    assert_eq!(text, "abcd\u{301}");
+}
+
+#[test]
+fn truncation_to_zero_width_leaves_nothing() {
+    let mut text = "\u{f2f1} 40%/1 MiB".to_string();
+    truncate_to_width(&mut text, 0);
+    assert_eq!(text, "");
+}
+
+#[test]
+fn segments_past_the_limit_are_counted() {
+    let mut text = "a | b | c".to_string();
+    limit_segments(&mut text, 1);
+    assert_eq!(text, "a | +2 more");
+
+    let mut text = "a | b | c".to_string();
+    limit_segments(&mut text, 0);
+    assert_eq!(text, "+3 more");
+
+    let mut text = "a | b".to_string();
+    limit_segments(&mut text, 2);
+    assert_eq!(text, "a | b");
+
+    let mut text = String::new();
+    limit_segments(&mut text, 0);
+    assert_eq!(text, "");
+}
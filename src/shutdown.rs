@@ -0,0 +1,59 @@
+use std::{
+    io::{self, Read, Write},
+    os::{fd::IntoRawFd, unix::net::UnixStream},
+    sync::atomic::{AtomicI32, Ordering},
+    thread,
+};
+
+/// Where the signal handler wakes up the thread that prints the final status.
+static WAKE_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// Clears the bar when waybar stops the module: on SIGTERM or SIGINT the given
+/// status is printed and the process exits right away, so that a long-poll in
+/// progress does not hold up shutdown.
+///
+/// The handler itself only wakes up a thread, which prints under the stdout
+/// lock so that the status never lands in the middle of another line.
+pub fn install(final_status: String) {
+    let (mut reader, writer) = match UnixStream::pair() {
+        Ok(pair) => pair,
+        Err(err) => {
+            log::warn!("Cannot clear the bar on shutdown: {}", err);
+            return;
+        }
+    };
+    let fd = writer.into_raw_fd();
+    if WAKE_FD
+        .compare_exchange(-1, fd, Ordering::SeqCst, Ordering::SeqCst)
+        .is_err()
+    {
+        return;
+    }
+
+    thread::spawn(move || {
+        if reader.read_exact(&mut [0]).is_err() {
+            return;
+        }
+        let mut stdout = io::stdout().lock();
+        let _ = writeln!(stdout, "{}", final_status);
+        let _ = stdout.flush();
+        // SAFETY: `_exit` skips destructors and atexit handlers, which could
+        // wait on the stdout lock held here.
+        unsafe { libc::_exit(0) }
+    });
+
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // SAFETY: the handler only calls async-signal-safe functions.
+    unsafe {
+        libc::signal(libc::SIGTERM, handler);
+        libc::signal(libc::SIGINT, handler);
+    }
+}
+
+extern "C" fn on_signal(_: libc::c_int) {
+    let fd = WAKE_FD.load(Ordering::SeqCst);
+    // SAFETY: `write` is async-signal-safe and `fd` is never closed.
+    unsafe {
+        libc::write(fd, [0u8].as_ptr().cast(), 1);
+    }
+}
@@ -0,0 +1,105 @@
+use anyhow::{Context, Result};
+use std::{fs, path::Path};
+
+/// The GUI/API settings from Syncthing's own `config.xml`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct GuiConfig {
+    pub address: Option<String>,
+    pub api_key: Option<String>,
+    pub url_base: Option<String>,
+    pub tls: bool,
+}
+
+impl GuiConfig {
+    pub fn read(path: &Path) -> Result<Self> {
+        let xml = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read Syncthing config {}", path.display()))?;
+
+        Ok(Self::parse(&xml))
+    }
+
+    /// Only the flat `<gui>` element is needed, so a full XML parser would be
+    /// overkill; values are unescaped for the few entities Syncthing writes.
+    fn parse(xml: &str) -> Self {
+        let Some(start) = xml.find("<gui") else {
+            return Self::default();
+        };
+        let gui = &xml[start..];
+        let gui = &gui[..gui.find("</gui>").unwrap_or(gui.len())];
+        let opening_tag = &gui[..gui.find('>').unwrap_or(gui.len())];
+
+        Self {
+            address: element(gui, "address"),
+            api_key: element(gui, "apikey"),
+            url_base: element(gui, "urlBase"),
+            tls: opening_tag.contains(r#"tls="true""#),
+        }
+    }
+
+    pub fn base_url(&self) -> Option<String> {
+        let address = self.address.as_deref()?;
+        let scheme = if self.tls { "https" } else { "http" };
+        let url_base = self
+            .url_base
+            .as_deref()
+            .map(|url_base| url_base.trim_matches('/'))
+            .filter(|url_base| !url_base.is_empty());
+
+        Some(match url_base {
+            Some(url_base) => format!("{}://{}/{}", scheme, address, url_base),
+            None => format!("{}://{}", scheme, address),
+        })
+    }
+}
+
+fn element(xml: &str, name: &str) -> Option<String> {
+    let open = format!("<{}>", name);
+    let close = format!("</{}>", name);
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find(&close)?;
+
+    let value = xml[start..end]
+        .trim()
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&");
+    Some(value).filter(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gui_settings_are_read_and_unescaped() {
+        let config = GuiConfig::parse(
+            r#"<configuration>
+                <device id="LAPTOP"><address>dynamic</address></device>
+                <gui enabled="true" tls="true">
+                    <address>127.0.0.1:8384</address>
+                    <apikey>a&amp;b&lt;c</apikey>
+                    <urlBase>/syncthing/</urlBase>
+                </gui>
+            </configuration>"#,
+        );
+
+        assert_eq!(config.api_key.as_deref(), Some("a&b<c"));
+        assert_eq!(
+            config.base_url().as_deref(),
+            Some("https://127.0.0.1:8384/syncthing")
+        );
+    }
+
+    #[test]
+    fn missing_or_empty_elements_are_none() {
+        let config =
+            GuiConfig::parse("<gui><address>localhost:8384</address><apikey></apikey></gui>");
+        assert_eq!(config.api_key, None);
+        assert_eq!(config.base_url().as_deref(), Some("http://localhost:8384"));
+
+        assert_eq!(GuiConfig::parse("<configuration/>"), GuiConfig::default());
+        assert_eq!(GuiConfig::default().base_url(), None);
+    }
+}
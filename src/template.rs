@@ -0,0 +1,184 @@
+use anyhow::{bail, Result};
+
+/// A value that can be placed in `--text-format` and friends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Icon,
+    Device,
+    Folder,
+    Percent,
+    NeedBytes,
+    TotalBytes,
+    Count,
+    /// How long ago the last folder got in sync, for `--idle-format`.
+    Ago,
+}
+
+const FIELDS: &[(&str, Field)] = &[
+    ("icon", Field::Icon),
+    ("device", Field::Device),
+    ("folder", Field::Folder),
+    ("percent", Field::Percent),
+    ("need_bytes", Field::NeedBytes),
+    ("total_bytes", Field::TotalBytes),
+    ("count", Field::Count),
+    ("ago", Field::Ago),
+];
+
+#[derive(Debug, Clone)]
+enum Part {
+    Literal(String),
+    Field(Field),
+}
+
+/// A format string with `{name}` placeholders, checked when the arguments are
+/// parsed so that a typo fails at startup rather than showing up in the bar.
+/// `{{` and `}}` stand for literal braces.
+#[derive(Debug, Clone)]
+pub struct Template(Vec<Part>);
+
+impl Template {
+    pub fn parse(input: &str) -> Result<Self> {
+        let template = Self::parse_any(input)?;
+        if template.uses(Field::Ago) {
+            bail!("{{ago}} is only available in --idle-format");
+        }
+
+        Ok(template)
+    }
+
+    fn parse_any(input: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = input.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            closed = true;
+                            break;
+                        }
+                        name.push(c);
+                    }
+                    if !closed {
+                        bail!("unclosed {{{}", name);
+                    }
+                    let Some((_, field)) = FIELDS.iter().find(|(n, _)| *n == name) else {
+                        bail!(
+                            "unknown placeholder {{{}}}, expected one of {}",
+                            name,
+                            FIELDS
+                                .iter()
+                                .map(|(name, _)| format!("{{{}}}", name))
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        );
+                    };
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Field(*field));
+                }
+                '}' => bail!("unmatched }}, write }}}} for a literal brace"),
+                _ => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        Ok(Self(parts))
+    }
+
+    /// A template for the overall progress, which has no single device or
+    /// folder to show.
+    pub fn parse_aggregate(input: &str) -> Result<Self> {
+        let template = Self::parse(input)?;
+        if template.uses(Field::Device) || template.uses(Field::Folder) {
+            bail!("{{device}} and {{folder}} are not available for the overall progress");
+        }
+
+        Ok(template)
+    }
+
+    /// A template for the text shown while everything is in sync.
+    pub fn parse_idle(input: &str) -> Result<Self> {
+        let template = Self::parse_any(input)?;
+        if template
+            .0
+            .iter()
+            .any(|part| matches!(part, Part::Field(f) if !matches!(f, Field::Icon | Field::Ago)))
+        {
+            bail!("only {{icon}} and {{ago}} are available when idle");
+        }
+
+        Ok(template)
+    }
+
+    /// Appends the rendered template to `out`, so that callers can reuse
+    /// their buffers.
+    pub fn render_into(&self, out: &mut String, value: impl Fn(Field) -> String) {
+        for part in &self.0 {
+            match part {
+                Part::Literal(literal) => out.push_str(literal),
+                Part::Field(field) => out.push_str(&value(*field)),
+            }
+        }
+    }
+
+    fn uses(&self, field: Field) -> bool {
+        self.0
+            .iter()
+            .any(|part| matches!(part, Part::Field(f) if *f == field))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &Template) -> String {
+        let mut out = String::from("> ");
+        template.render_into(&mut out, |field| format!("{:?}", field));
+        out
+    }
+
+    #[test]
+    fn fields_and_literals_are_rendered_in_order() {
+        let template = Template::parse("{icon} {percent}% of {folder}").unwrap();
+        assert_eq!(render(&template), "> Icon Percent% of Folder");
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        let template = Template::parse("{{{count}}}").unwrap();
+        assert_eq!(render(&template), "> {Count}");
+    }
+
+    #[test]
+    fn malformed_templates_are_rejected() {
+        assert!(Template::parse("{percent").is_err());
+        assert!(Template::parse("percent}").is_err());
+        assert!(Template::parse("{percentage}").is_err());
+    }
+
+    #[test]
+    fn fields_are_checked_per_template_kind() {
+        assert!(Template::parse("{ago}").is_err());
+        assert!(Template::parse_aggregate("{device}").is_err());
+        assert!(Template::parse_aggregate("{count} at {percent}%").is_ok());
+        assert!(Template::parse_idle("{icon} synced {ago}").is_ok());
+        assert!(Template::parse_idle("{percent}").is_err());
+    }
+}
@@ -0,0 +1,210 @@
+use reqwest::{header::HeaderMap, StatusCode};
+use std::{
+    error, fmt,
+    io::{self, Read, Write},
+    os::unix::net::UnixStream,
+    path::Path,
+    time::Duration,
+};
+
+/// What came back from Syncthing over its Unix socket.
+#[derive(Debug)]
+pub struct Response {
+    pub status: StatusCode,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Status(StatusCode),
+    Decode(serde_json::Error),
+    Protocol(&'static str),
+}
+
+impl Error {
+    /// Whether trying again later may get through.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Io(_) | Error::Protocol(_) => true,
+            Error::Status(status) => status.is_server_error(),
+            Error::Decode(_) => false,
+        }
+    }
+
+    pub fn is_timeout(&self) -> bool {
+        matches!(
+            self,
+            Error::Io(err) if matches!(err.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock)
+        )
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "Failed to talk to Syncthing's socket: {}", err),
+            Error::Status(status) => write!(f, "HTTP status {}", status),
+            Error::Decode(err) => write!(f, "error decoding response body: {}", err),
+            Error::Protocol(problem) => write!(f, "Invalid HTTP response: {}", problem),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Decode(err) => Some(err),
+            Error::Status(_) | Error::Protocol(_) => None,
+        }
+    }
+}
+
+/// A plain HTTP/1.1 GET on a fresh connection, which is all the Syncthing API
+/// needs. `timeout` bounds every read and write on the socket.
+pub fn get(
+    socket: &Path,
+    path: &str,
+    headers: &HeaderMap,
+    timeout: Duration,
+) -> Result<Response, Error> {
+    let mut stream = UnixStream::connect(socket).map_err(Error::Io)?;
+    stream.set_read_timeout(Some(timeout)).map_err(Error::Io)?;
+    stream.set_write_timeout(Some(timeout)).map_err(Error::Io)?;
+
+    let mut request = format!("GET /{} HTTP/1.1\r\nHost: localhost\r\n", path).into_bytes();
+    for (name, value) in headers {
+        request.extend_from_slice(name.as_str().as_bytes());
+        request.extend_from_slice(b": ");
+        request.extend_from_slice(value.as_bytes());
+        request.extend_from_slice(b"\r\n");
+    }
+    request.extend_from_slice(b"Connection: close\r\n\r\n");
+    stream.write_all(&request).map_err(Error::Io)?;
+
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).map_err(Error::Io)?;
+    parse_response(&raw)
+}
+
+fn parse_response(raw: &[u8]) -> Result<Response, Error> {
+    let end = raw
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or(Error::Protocol(
+            "connection closed before the headers ended",
+        ))?;
+    let head =
+        std::str::from_utf8(&raw[..end]).map_err(|_| Error::Protocol("headers are not UTF-8"))?;
+    let mut body = &raw[end + 4..];
+
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse::<u16>().ok())
+        .and_then(|code| StatusCode::from_u16(code).ok())
+        .ok_or(Error::Protocol("missing status line"))?;
+
+    let mut content_type = None;
+    let mut chunked = false;
+    for (name, value) in lines.filter_map(|line| line.split_once(':')) {
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-type") {
+            content_type = Some(value.to_string());
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value.eq_ignore_ascii_case("chunked");
+        } else if name.eq_ignore_ascii_case("content-length") {
+            let length = value
+                .parse::<usize>()
+                .map_err(|_| Error::Protocol("invalid Content-Length"))?;
+            body = body
+                .get(..length)
+                .ok_or(Error::Protocol("connection closed before the body ended"))?;
+        }
+    }
+
+    Ok(Response {
+        status,
+        content_type,
+        body: if chunked {
+            dechunk(body)?
+        } else {
+            body.to_vec()
+        },
+    })
+}
+
+fn dechunk(mut chunked: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = chunked
+            .windows(2)
+            .position(|window| window == b"\r\n")
+            .ok_or(Error::Protocol("connection closed before the last chunk"))?;
+        let size = std::str::from_utf8(&chunked[..line_end])
+            .ok()
+            // Chunk extensions follow a `;`.
+            .and_then(|line| line.split(';').next())
+            .and_then(|size| usize::from_str_radix(size.trim(), 16).ok())
+            .ok_or(Error::Protocol("invalid chunk size"))?;
+        if size == 0 {
+            return Ok(body);
+        }
+
+        chunked = &chunked[line_end + 2..];
+        let chunk = chunked
+            .get(..size)
+            .ok_or(Error::Protocol("connection closed before the last chunk"))?;
+        body.extend_from_slice(chunk);
+        chunked = chunked.get(size + 2..).unwrap_or_default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_extensions_are_ignored() {
+        let body = dechunk(b"4;name=value\r\n{\"a\"\r\n3\r\n:1}\r\n0\r\n\r\n").unwrap();
+        assert_eq!(body, b"{\"a\":1}");
+    }
+
+    #[test]
+    fn truncated_chunks_are_an_error() {
+        assert!(matches!(dechunk(b"a\r\nshort"), Err(Error::Protocol(_))));
+        assert!(matches!(dechunk(b"zz\r\n"), Err(Error::Protocol(_))));
+    }
+
+    #[test]
+    fn body_ends_at_the_content_length() {
+        let response = parse_response(
+            b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 2\r\n\r\n[]trailing",
+        )
+        .unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.content_type.as_deref(), Some("application/json"));
+        assert_eq!(response.body, b"[]");
+    }
+
+    #[test]
+    fn content_length_past_the_body_is_an_error() {
+        assert!(matches!(
+            parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n[]"),
+            Err(Error::Protocol(_))
+        ));
+    }
+
+    #[test]
+    fn chunked_responses_are_decoded() {
+        let response = parse_response(
+            b"HTTP/1.1 404 Not Found\r\nTransfer-Encoding: chunked\r\n\r\n9\r\nnot found\r\n0\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+        assert_eq!(response.body, b"not found");
+    }
+}
@@ -0,0 +1,547 @@
+use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    os::unix::net::UnixListener,
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Output, Stdio},
+    sync::{
+        mpsc::{self, Receiver},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const LINE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A minimal HTTP server that plays back canned Syncthing API responses.
+///
+/// Every path serves its queued bodies in order and keeps repeating the last
+/// one, except `rest/events`, which holds the connection open like an idle
+/// long-poll once its script is exhausted.
+pub struct StubServer {
+    base_url: String,
+    requests: Arc<Mutex<Vec<String>>>,
+    api_keys: Arc<Mutex<HashSet<String>>>,
+    concurrency: Arc<Mutex<HashMap<String, Concurrency>>>,
+}
+
+/// Requests to one path being answered right now, and the most seen at once.
+#[derive(Default, Clone, Copy)]
+struct Concurrency {
+    current: usize,
+    peak: usize,
+}
+
+struct InFlight<'a> {
+    path: String,
+    concurrency: &'a Mutex<HashMap<String, Concurrency>>,
+}
+
+impl<'a> InFlight<'a> {
+    fn start(path: &str, concurrency: &'a Mutex<HashMap<String, Concurrency>>) -> Self {
+        let mut paths = concurrency.lock().unwrap();
+        let entry = paths.entry(path.to_string()).or_default();
+        entry.current += 1;
+        entry.peak = entry.peak.max(entry.current);
+
+        Self {
+            path: path.to_string(),
+            concurrency,
+        }
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        if let Some(entry) = self.concurrency.lock().unwrap().get_mut(&self.path) {
+            entry.current -= 1;
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Reply {
+    status: u16,
+    body: String,
+    delay: Duration,
+}
+
+#[derive(Default)]
+pub struct Routes {
+    responses: HashMap<String, VecDeque<Reply>>,
+}
+
+impl Routes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn respond(self, path: &str, body: Value) -> Self {
+        self.respond_with(path, 200, &body.to_string())
+    }
+
+    pub fn respond_with(self, path: &str, status: u16, body: &str) -> Self {
+        self.reply(path, status, body, Duration::ZERO)
+    }
+
+    /// Answer only after `delay`, like a long-poll that eventually sees events.
+    pub fn respond_after(self, path: &str, delay: Duration, body: Value) -> Self {
+        self.reply(path, 200, &body.to_string(), delay)
+    }
+
+    fn reply(mut self, path: &str, status: u16, body: &str, delay: Duration) -> Self {
+        self.responses
+            .entry(path.to_string())
+            .or_default()
+            .push_back(Reply {
+                status,
+                body: body.to_string(),
+                delay,
+            });
+        self
+    }
+
+    /// Close the connection without answering, like a network blip would.
+    pub fn drop_connection(self, path: &str) -> Self {
+        self.respond_with(path, 0, "")
+    }
+
+    /// Close the connection without answering once `delay` has passed.
+    pub fn drop_connection_after(self, path: &str, delay: Duration) -> Self {
+        self.reply(path, 0, "", delay)
+    }
+}
+
+impl StubServer {
+    pub fn start(routes: Routes) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind stub server");
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        Self::serve(base_url, routes, move || {
+            listener.accept().ok().map(|(stream, _)| stream)
+        })
+    }
+
+    /// The same server listening on a Unix socket at `path`.
+    pub fn start_unix(routes: Routes, path: &Path) -> Self {
+        let _ = std::fs::remove_file(path);
+        let listener = UnixListener::bind(path).expect("bind stub socket");
+        let base_url = format!("unix://{}", path.display());
+        Self::serve(base_url, routes, move || {
+            listener.accept().ok().map(|(stream, _)| stream)
+        })
+    }
+
+    fn serve<S: Read + Write + Send + 'static>(
+        base_url: String,
+        routes: Routes,
+        mut accept: impl FnMut() -> Option<S> + Send + 'static,
+    ) -> Self {
+        let responses = Arc::new(Mutex::new(routes.responses));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let api_keys = Arc::new(Mutex::new(HashSet::new()));
+        let concurrency = Arc::new(Mutex::new(HashMap::new()));
+
+        let requests_log = requests.clone();
+        let api_keys_log = api_keys.clone();
+        let concurrency_log = concurrency.clone();
+        thread::spawn(move || {
+            while let Some(stream) = accept() {
+                let responses = responses.clone();
+                let log = RequestLog {
+                    requests: requests_log.clone(),
+                    api_keys: api_keys_log.clone(),
+                    concurrency: concurrency_log.clone(),
+                };
+                thread::spawn(move || handle_connection(stream, &responses, &log));
+            }
+        });
+
+        Self {
+            base_url,
+            requests,
+            api_keys,
+            concurrency,
+        }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Waits until at least `count` requests made it here that `matches`
+    /// accepts, for when no new status shows that they did.
+    pub fn wait_for_requests(&self, count: usize, matches: impl Fn(&str) -> bool) {
+        let deadline = Instant::now() + LINE_TIMEOUT;
+        while self.requests().iter().filter(|r| matches(r)).count() < count {
+            assert!(Instant::now() < deadline, "timed out waiting for requests");
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    /// Every distinct bearer token requests were sent with.
+    pub fn api_keys(&self) -> HashSet<String> {
+        self.api_keys.lock().unwrap().clone()
+    }
+
+    /// The most requests to `path` that were being answered at the same time.
+    pub fn peak_concurrency(&self, path: &str) -> usize {
+        self.concurrency
+            .lock()
+            .unwrap()
+            .get(path)
+            .map_or(0, |entry| entry.peak)
+    }
+}
+
+struct RequestLog {
+    requests: Arc<Mutex<Vec<String>>>,
+    api_keys: Arc<Mutex<HashSet<String>>>,
+    concurrency: Arc<Mutex<HashMap<String, Concurrency>>>,
+}
+
+fn handle_connection(
+    stream: impl Read + Write,
+    responses: &Mutex<HashMap<String, VecDeque<Reply>>>,
+    log: &RequestLog,
+) {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).is_err() || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+            if name.eq_ignore_ascii_case("authorization") {
+                if let Some(key) = value.trim().strip_prefix("Bearer ") {
+                    log.api_keys.lock().unwrap().insert(key.to_string());
+                }
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    let _ = reader.read_exact(&mut body);
+
+    let target = request_line
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or("/")
+        .to_string();
+    log.requests.lock().unwrap().push(target.clone());
+
+    let path = target
+        .trim_start_matches('/')
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    let _in_flight = InFlight::start(&path, &log.concurrency);
+
+    let response = {
+        let mut responses = responses.lock().unwrap();
+        match responses.get_mut(&path) {
+            Some(queue) if queue.len() > 1 => queue.pop_front(),
+            Some(queue) if path.ends_with("rest/events") => queue.pop_front(),
+            Some(queue) => queue.front().cloned(),
+            None => Some(Reply {
+                status: 404,
+                body: "404 page not found".to_string(),
+                delay: Duration::ZERO,
+            }),
+        }
+    };
+
+    let Some(Reply {
+        status,
+        body,
+        delay,
+    }) = response.or_else(|| idle_long_poll(&target))
+    else {
+        return;
+    };
+    thread::sleep(delay);
+    if status == 0 {
+        return;
+    }
+
+    let content_type = if body.starts_with('<') {
+        "text/html; charset=utf-8"
+    } else if status == 200 {
+        "application/json"
+    } else {
+        "text/plain; charset=utf-8"
+    };
+
+    let _ = write!(
+        reader.get_mut(),
+        "HTTP/1.1 {} Stub\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+}
+
+/// Behave like an idle long-poll: answer with no events once the requested
+/// `timeout` elapses, or never if none was given.
+fn idle_long_poll(target: &str) -> Option<Reply> {
+    let timeout = target
+        .split(['?', '&'])
+        .find_map(|param| param.strip_prefix("timeout="))
+        .and_then(|value| value.parse::<f64>().ok());
+
+    match timeout {
+        Some(secs) => Some(Reply {
+            status: 200,
+            body: "[]".to_string(),
+            delay: Duration::from_secs_f64(secs),
+        }),
+        None => {
+            thread::sleep(Duration::from_secs(3600));
+            None
+        }
+    }
+}
+
+/// A running `waybar-syncthing` process whose stdout is read line by line.
+pub struct Module {
+    child: Child,
+    lines: Receiver<String>,
+}
+
+impl Module {
+    pub fn spawn(server: &StubServer, extra_args: &[&str]) -> Self {
+        let mut args = vec!["--api-key", "stub-api-key", "--base-url", server.base_url()];
+        args.extend(extra_args);
+        Self::spawn_with_args(&args)
+    }
+
+    /// Starts `waybar-syncthing` with exactly the given arguments.
+    pub fn spawn_with_args(args: &[&str]) -> Self {
+        Self::spawn_with_env(args, &[])
+    }
+
+    /// Starts `waybar-syncthing` with some environment variables, such as
+    /// where to look for its config file.
+    pub fn spawn_with_env(args: &[&str], env: &[(&str, &Path)]) -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_waybar-syncthing"))
+            .args(args)
+            .envs(isolated_env())
+            .envs(env.iter().copied())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("spawn waybar-syncthing");
+
+        let stdout = child.stdout.take().unwrap();
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self { child, lines }
+    }
+
+    pub fn next_line(&self) -> String {
+        self.lines
+            .recv_timeout(LINE_TIMEOUT)
+            .expect("timed out waiting for output")
+    }
+
+    pub fn next_status(&self) -> Value {
+        serde_json::from_str(&self.next_line()).expect("output is valid JSON")
+    }
+
+    /// Whether anything was printed within `timeout`.
+    pub fn prints_within(&self, timeout: Duration) -> bool {
+        self.lines.recv_timeout(timeout).is_ok()
+    }
+
+    pub fn send_line(&mut self, line: &str) {
+        let stdin = self.child.stdin.as_mut().expect("stdin is piped");
+        writeln!(stdin, "{}", line).expect("write to stdin");
+    }
+
+    /// Asks the module to stop like waybar does, and waits for it to exit.
+    pub fn terminate(&mut self) -> ExitStatus {
+        Command::new("kill")
+            .args(["-TERM", &self.child.id().to_string()])
+            .status()
+            .expect("run kill");
+        self.child.wait().expect("wait for waybar-syncthing")
+    }
+
+    pub fn close_stdin(&mut self) {
+        drop(self.child.stdin.take());
+    }
+}
+
+/// Runs `waybar-syncthing` until it exits on its own.
+pub fn run(server: &StubServer, extra_args: &[&str]) -> Output {
+    let mut args = vec!["--base-url", server.base_url()];
+    args.extend(extra_args);
+    run_with_args(&args)
+}
+
+/// Runs `waybar-syncthing` with only an API key and the given arguments.
+pub fn run_with_args(args: &[&str]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_waybar-syncthing"))
+        .args(["--api-key", "stub-api-key"])
+        .args(args)
+        .envs(isolated_env())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn waybar-syncthing");
+
+    let deadline = Instant::now() + LINE_TIMEOUT;
+    while child.try_wait().unwrap().is_none() {
+        if Instant::now() > deadline {
+            let _ = child.kill();
+            panic!("waybar-syncthing did not exit");
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    child.wait_with_output().unwrap()
+}
+
+/// Keeps the config and cache files of whoever runs the tests out of them.
+fn isolated_env() -> [(&'static str, PathBuf); 2] {
+    let dir = std::env::temp_dir().join("waybar-syncthing-isolated");
+    [("XDG_CONFIG_HOME", dir.clone()), ("XDG_CACHE_HOME", dir)]
+}
+
+/// Where an instance at `base_url` keeps its `name` cache file under
+/// `cache`, keyed by an FNV-1a hash of the address.
+pub fn cache_file(cache: &Path, name: &str, base_url: &str) -> PathBuf {
+    let hash = base_url
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+    cache
+        .join("waybar-syncthing")
+        .join(format!("{}-{:016x}.json", name, hash))
+}
+
+impl Drop for Module {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+pub fn system_status(start_time: &str) -> Value {
+    serde_json::json!({ "myID": "LOCAL-ID", "startTime": start_time })
+}
+
+pub fn config(devices: &[(&str, &str)], folders: &[(&str, &str)]) -> Value {
+    serde_json::json!({
+        "devices": devices
+            .iter()
+            .map(|(id, name)| serde_json::json!({ "deviceID": id, "name": name }))
+            .collect::<Vec<_>>(),
+        "folders": folders
+            .iter()
+            .map(|(id, label)| serde_json::json!({ "id": id, "label": label }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+pub fn connections(connected: &[(&str, bool)]) -> Value {
+    serde_json::json!({
+        "connections": connected
+            .iter()
+            .map(|(id, connected)| (id.to_string(), serde_json::json!({ "connected": connected })))
+            .collect::<serde_json::Map<_, _>>(),
+    })
+}
+
+pub fn folder_completion(
+    id: u64,
+    device: &str,
+    folder: &str,
+    completion: f64,
+    need_bytes: u64,
+) -> Value {
+    serde_json::json!({
+        "id": id,
+        "type": "FolderCompletion",
+        "data": {
+            "device": device,
+            "folder": folder,
+            "completion": completion,
+            "needBytes": need_bytes,
+        },
+    })
+}
+
+pub fn with_global_bytes(mut event: Value, global_bytes: u64) -> Value {
+    event["data"]["globalBytes"] = global_bytes.into();
+    event
+}
+
+pub fn device_connected(id: u64, device: &str) -> Value {
+    serde_json::json!({
+        "id": id,
+        "type": "DeviceConnected",
+        "data": { "id": device, "addr": "192.0.2.1:22000" },
+    })
+}
+
+pub fn device_disconnected(id: u64, device: &str) -> Value {
+    serde_json::json!({
+        "id": id,
+        "type": "DeviceDisconnected",
+        "data": { "id": device },
+    })
+}
+
+pub fn state_changed(id: u64, folder: &str, to: &str) -> Value {
+    serde_json::json!({
+        "id": id,
+        "type": "StateChanged",
+        "data": { "folder": folder, "from": "idle", "to": to },
+    })
+}
+
+pub fn folder_summary(id: u64, folder: &str, state: &str, need_bytes: u64) -> Value {
+    serde_json::json!({
+        "id": id,
+        "type": "FolderSummary",
+        "data": {
+            "folder": folder,
+            "summary": { "globalBytes": 4 * 1024 * 1024, "needBytes": need_bytes, "state": state },
+        },
+    })
+}
+
+/// How many folder lines the tooltip has, leaving out the device headers.
+pub fn tooltip_entries(status: &Value) -> usize {
+    status["tooltip"]
+        .as_str()
+        .unwrap()
+        .lines()
+        .filter(|line| line.starts_with("  "))
+        .count()
+}
@@ -0,0 +1,3375 @@
+mod common;
+
+use common::{
+    cache_file, config, connections, device_connected, device_disconnected, folder_completion,
+    folder_summary, run, run_with_args, state_changed, system_status, tooltip_entries,
+    with_global_bytes, Module, Routes, StubServer,
+};
+use serde_json::json;
+use std::{fs, time::Duration};
+use unicode_width::UnicodeWidthStr;
+
+const LAPTOP: &str = "LAPTOP-ID";
+const PHONE: &str = "PHONE-ID";
+const PHOTOS: &str = "photos-id";
+const STARTED: &str = "2024-01-01T10:00:00+01:00";
+
+fn syncthing() -> Routes {
+    Routes::new().respond("rest/system/status", system_status(STARTED))
+}
+
+fn routes() -> Routes {
+    syncthing()
+        .respond(
+            "rest/system/config",
+            config(
+                &[(LAPTOP, "laptop"), (PHONE, "phone")],
+                &[(PHOTOS, "Photos")],
+            ),
+        )
+        .respond(
+            "rest/system/connections",
+            connections(&[(LAPTOP, true), (PHONE, true)]),
+        )
+}
+
+#[test]
+fn pending_folder_is_shown_with_names() {
+    let server = StubServer::start(routes().respond(
+        "rest/events",
+        json!([folder_completion(1, LAPTOP, PHOTOS, 42.5, 3 * 1024 * 1024)]),
+    ));
+    let module = Module::spawn(&server, &[]);
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{f2f1} 43%/3 MiB");
+    assert_eq!(status["tooltip"], "<b>laptop</b>\n  Photos     43% (3 MiB)");
+}
+
+#[test]
+fn percent_round_picks_the_rounding() {
+    for (mode, percent) in [("floor", 98), ("round", 99), ("ceil", 99)] {
+        let server = StubServer::start(routes().respond(
+            "rest/events",
+            json!([folder_completion(1, LAPTOP, PHOTOS, 98.6, 1024)]),
+        ));
+        let module = Module::spawn(&server, &["--percent-round", mode]);
+        let status = module.next_status();
+        assert_eq!(
+            status["text"],
+            format!("\u{f2f1} {percent}%/1 KiB"),
+            "{mode}"
+        );
+        assert_eq!(
+            status["tooltip"],
+            format!("<b>laptop</b>\n  Photos     {percent}% (1 KiB)"),
+            "{mode}"
+        );
+    }
+}
+
+#[test]
+fn keep_disconnected_marks_instead_of_dropping() {
+    let server = StubServer::start(
+        routes()
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+            )
+            .respond("rest/events", json!([device_disconnected(2, LAPTOP)]))
+            .respond(
+                "rest/system/connections",
+                connections(&[(LAPTOP, false), (PHONE, true)]),
+            ),
+    );
+    let module = Module::spawn(&server, &["--keep-disconnected"]);
+
+    assert_eq!(
+        module.next_status()["tooltip"],
+        "<b>laptop</b>\n  Photos     40% (1 MiB)"
+    );
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{f2f1} 40%/1 MiB");
+    assert_eq!(
+        status["tooltip"],
+        "<b>laptop</b> (disconnected)\n  Photos     40% (1 MiB)"
+    );
+}
+
+#[test]
+fn disconnect_then_complete_clears_pending() {
+    let server = StubServer::start(
+        routes()
+            .respond(
+                "rest/events",
+                json!([
+                    folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024),
+                    folder_completion(2, PHONE, PHOTOS, 60., 2 * 1024 * 1024),
+                ]),
+            )
+            .respond("rest/events", json!([device_disconnected(3, LAPTOP)]))
+            .respond(
+                "rest/events",
+                json!([folder_completion(4, PHONE, PHOTOS, 100., 0)]),
+            ),
+    );
+    let module = Module::spawn(&server, &[]);
+
+    let status = module.next_status();
+    let tooltip = status["tooltip"].as_str().unwrap();
+    assert!(tooltip.contains("<b>laptop</b>"), "{tooltip}");
+    assert!(tooltip.contains("<b>phone</b>"), "{tooltip}");
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{f2f1} 60%/2 MiB");
+    assert_eq!(status["tooltip"], "<b>phone</b>\n  Photos     60% (2 MiB)");
+
+    let status = module.next_status();
+    assert_eq!(
+        status,
+        json!({ "text": "", "tooltip": "", "class": "idle" })
+    );
+
+    let requests = server.requests();
+    assert!(requests
+        .iter()
+        .any(|r| r.starts_with("/rest/events?since=3&")));
+}
+
+#[test]
+fn unknown_device_refreshes_the_config() {
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                config(&[(LAPTOP, "laptop")], &[(PHOTOS, "Photos")]),
+            )
+            .respond(
+                "rest/system/config",
+                config(
+                    &[(LAPTOP, "laptop"), (PHONE, "phone")],
+                    &[(PHOTOS, "Photos")],
+                ),
+            )
+            .respond(
+                "rest/system/connections",
+                connections(&[(LAPTOP, true), (PHONE, true)]),
+            )
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+            )
+            .respond(
+                "rest/events",
+                json!([folder_completion(2, LAPTOP, PHOTOS, 50., 1024 * 1024)]),
+            )
+            .respond(
+                "rest/events",
+                json!([folder_completion(3, PHONE, PHOTOS, 60., 1024 * 1024)]),
+            ),
+    );
+    let module = Module::spawn(&server, &[]);
+    let config_requests = || {
+        server
+            .requests()
+            .iter()
+            .filter(|r| r.starts_with("/rest/system/config"))
+            .count()
+    };
+
+    module.next_status();
+    module.next_status();
+    // Known devices and folders need no refresh.
+    assert_eq!(config_requests(), 1);
+
+    assert_eq!(
+        module.next_status()["tooltip"],
+        "<b>laptop</b>\n  Photos     50% (1 MiB)\n<b>phone</b>\n  Photos     60% (1 MiB)"
+    );
+    assert_eq!(config_requests(), 2);
+}
+
+#[test]
+fn disconnected_device_in_connections_is_pruned() {
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                config(&[(LAPTOP, "laptop")], &[(PHOTOS, "Photos")]),
+            )
+            .respond("rest/system/connections", connections(&[(LAPTOP, false)]))
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 10., 1024 * 1024)]),
+            ),
+    );
+    let module = Module::spawn(&server, &[]);
+
+    assert_eq!(
+        module.next_status(),
+        json!({ "text": "", "tooltip": "", "class": "idle" })
+    );
+}
+
+#[test]
+fn cluster_view_groups_folder_across_devices() {
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                json!({
+                    "devices": [
+                        { "deviceID": "LOCAL-ID", "name": "local" },
+                        { "deviceID": LAPTOP, "name": "laptop" },
+                        { "deviceID": PHONE, "name": "phone" },
+                        { "deviceID": "NAS-ID", "name": "nas" },
+                    ],
+                    "folders": [{
+                        "id": PHOTOS,
+                        "label": "Photos",
+                        "devices": [
+                            { "deviceID": "LOCAL-ID" },
+                            { "deviceID": LAPTOP },
+                            { "deviceID": PHONE },
+                            { "deviceID": "NAS-ID" },
+                        ],
+                    }],
+                }),
+            )
+            .respond(
+                "rest/system/connections",
+                connections(&[(LAPTOP, true), (PHONE, true), ("NAS-ID", true)]),
+            )
+            .respond(
+                "rest/events",
+                json!([
+                    folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024),
+                    folder_completion(2, PHONE, PHOTOS, 75., 2 * 1024 * 1024),
+                ]),
+            ),
+    );
+    let module = Module::spawn(&server, &["--cluster-view"]);
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{f2f1} 40%/3 MiB");
+    assert_eq!(status["tooltip"], "Photos: 2/3 peers, min 40%");
+}
+
+#[test]
+fn network_blip_keeps_event_position() {
+    let server = StubServer::start(
+        routes()
+            .respond(
+                "rest/events",
+                json!([folder_completion(7, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+            )
+            .drop_connection("rest/events")
+            .respond(
+                "rest/events",
+                json!([folder_completion(8, LAPTOP, PHOTOS, 50., 1024 * 1024)]),
+            ),
+    );
+    let module = Module::spawn(&server, &[]);
+
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 40%/1 MiB");
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{f2f1} 40%/1 MiB");
+    assert_eq!(status["class"], "disconnected");
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 50%/1 MiB");
+
+    let events = server
+        .requests()
+        .into_iter()
+        .filter(|r| r.starts_with("/rest/events"))
+        .collect::<Vec<_>>();
+    assert!(events[1].starts_with("/rest/events?since=7&"), "{events:?}");
+    assert!(events[2].starts_with("/rest/events?since=7&"), "{events:?}");
+}
+
+#[test]
+fn syncthing_restart_resets_event_position() {
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/status",
+                system_status("2024-01-01T12:00:00+01:00"),
+            )
+            .respond(
+                "rest/system/config",
+                config(&[(LAPTOP, "laptop")], &[(PHOTOS, "Photos")]),
+            )
+            .respond("rest/system/connections", connections(&[(LAPTOP, true)]))
+            .respond(
+                "rest/events",
+                json!([folder_completion(7, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+            )
+            .drop_connection("rest/events")
+            .respond("rest/events", json!([])),
+    );
+    let module = Module::spawn(&server, &[]);
+
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 40%/1 MiB");
+    assert_eq!(module.next_status()["class"], "disconnected");
+    assert_eq!(module.next_status()["text"], "");
+
+    let events = server
+        .requests()
+        .into_iter()
+        .filter(|r| r.starts_with("/rest/events"))
+        .collect::<Vec<_>>();
+    assert!(events[2].starts_with("/rest/events?since=0&"), "{events:?}");
+}
+
+#[test]
+fn unchanged_status_is_not_printed_again() {
+    let server = StubServer::start(
+        routes()
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+            )
+            .respond(
+                "rest/events",
+                json!([folder_completion(2, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+            )
+            .respond(
+                "rest/events",
+                json!([folder_completion(3, LAPTOP, PHOTOS, 100., 0)]),
+            ),
+    );
+    let module = Module::spawn(&server, &[]);
+
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 40%/1 MiB");
+    assert_eq!(
+        module.next_status(),
+        json!({ "text": "", "tooltip": "", "class": "idle" })
+    );
+}
+
+#[test]
+fn progress_bar_text_matches_percentage() {
+    const MIB: u64 = 1024 * 1024;
+    let server = StubServer::start(routes().respond(
+        "rest/events",
+        json!([
+            with_global_bytes(folder_completion(1, LAPTOP, PHOTOS, 50., 5 * MIB), 10 * MIB),
+            with_global_bytes(folder_completion(2, PHONE, PHOTOS, 90., MIB), 10 * MIB),
+        ]),
+    ));
+    let module = Module::spawn(&server, &["--progress-bar"]);
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{f2f1} 70%/6 MiB");
+    assert_eq!(status["percentage"], 70);
+}
+
+#[test]
+fn folders_from_devices_hides_unshared_folders() {
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                json!({
+                    "devices": [
+                        { "deviceID": LAPTOP, "name": "laptop" },
+                        { "deviceID": PHONE, "name": "phone" },
+                    ],
+                    "folders": [{
+                        "id": PHOTOS,
+                        "label": "Photos",
+                        "devices": [{ "deviceID": "LOCAL-ID" }, { "deviceID": LAPTOP }],
+                    }],
+                }),
+            )
+            .respond(
+                "rest/system/connections",
+                connections(&[(LAPTOP, true), (PHONE, true)]),
+            )
+            .respond(
+                "rest/events",
+                json!([
+                    folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024),
+                    folder_completion(2, PHONE, PHOTOS, 60., 1024 * 1024),
+                ]),
+            ),
+    );
+    let module = Module::spawn(&server, &["--folders-from-devices"]);
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{f2f1} 40%/1 MiB");
+    assert_eq!(status["tooltip"], "<b>laptop</b>\n  Photos     40% (1 MiB)");
+}
+
+#[test]
+fn empty_config_keeps_previous_names() {
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                config(&[(LAPTOP, "laptop")], &[(PHOTOS, "Photos")]),
+            )
+            .respond(
+                "rest/system/config",
+                json!({ "devices": [], "folders": [] }),
+            )
+            .respond(
+                "rest/system/connections",
+                connections(&[(LAPTOP, true), (PHONE, true)]),
+            )
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+            )
+            .respond(
+                "rest/events",
+                json!([folder_completion(2, PHONE, PHOTOS, 100., 0)]),
+            ),
+    );
+    let module = Module::spawn(&server, &[]);
+
+    let expected = "<b>laptop</b>\n  Photos     40% (1 MiB)";
+    assert_eq!(module.next_status()["tooltip"], expected);
+    server.wait_for_requests(1, |r| r.starts_with("/rest/events?since=2&"));
+    // The names are unchanged, and so is the status.
+    assert!(!module.prints_within(Duration::from_millis(200)));
+    assert_eq!(
+        server
+            .requests()
+            .iter()
+            .filter(|r| r.as_str() == "/rest/system/config")
+            .count(),
+        2
+    );
+}
+
+#[test]
+fn text_and_tooltip_use_separate_byte_formats() {
+    const GIB: u64 = 1024 * 1024 * 1024;
+    let server = StubServer::start(routes().respond(
+        "rest/events",
+        json!([folder_completion(1, LAPTOP, PHOTOS, 10., 11 * GIB / 4)]),
+    ));
+    let module = Module::spawn(&server, &["--text-bytes", "short"]);
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{f2f1} 10%/3G");
+    assert_eq!(
+        status["tooltip"],
+        "<b>laptop</b>\n  Photos     10% (2.75 GiB)"
+    );
+}
+
+#[test]
+fn html_login_page_reports_api_key_problem() {
+    let server = StubServer::start(Routes::new().respond_with(
+        "rest/system/status",
+        200,
+        "<!DOCTYPE html><html><body>Syncthing</body></html>",
+    ));
+
+    let output = run(&server, &[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("API key is probably missing or wrong"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn restricted_system_status_leaves_restarts_undetected() {
+    let server = StubServer::start(
+        Routes::new()
+            .respond_with("rest/system/status", 404, "404 page not found")
+            .respond(
+                "rest/system/config",
+                config(&[(LAPTOP, "laptop")], &[(PHOTOS, "Photos")]),
+            )
+            .respond("rest/system/connections", connections(&[(LAPTOP, true)]))
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+            ),
+    );
+    let module = Module::spawn(&server, &[]);
+
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 40%/1 MiB");
+}
+
+#[test]
+fn completion_events_only_skips_connections() {
+    let server = StubServer::start(
+        routes()
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 10., 1024 * 1024)]),
+            )
+            .respond("rest/events", json!([device_disconnected(2, LAPTOP)])),
+    );
+    let module = Module::spawn(&server, &["--completion-events-only"]);
+
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 10%/1 MiB");
+    assert_eq!(module.next_status()["text"], "");
+    assert!(!server
+        .requests()
+        .iter()
+        .any(|r| r.starts_with("/rest/system/connections")));
+}
+
+#[test]
+fn device_without_name_shows_short_id() {
+    const UNNAMED: &str = "MFZWI3D-BONSGYC-YLTMRWG-C43ENR5-QXGZDMM-FZWI3DP-BONSGYY-LTMRWAD";
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                config(&[(UNNAMED, UNNAMED)], &[(PHOTOS, "Photos")]),
+            )
+            .respond("rest/system/connections", connections(&[(UNNAMED, true)]))
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, UNNAMED, PHOTOS, 10., 1024 * 1024)]),
+            ),
+    );
+    let module = Module::spawn(&server, &[]);
+
+    assert_eq!(
+        module.next_status()["tooltip"],
+        "<b>MFZWI3D</b>\n  Photos     10% (1 MiB)"
+    );
+}
+
+#[test]
+fn text_is_truncated_to_max_length() {
+    let server = StubServer::start(routes().respond(
+        "rest/events",
+        json!([folder_completion(1, LAPTOP, PHOTOS, 42., 3 * 1024 * 1024)]),
+    ));
+    let module = Module::spawn(&server, &["--text-max-length", "7"]);
+
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 42%/…");
+}
+
+#[test]
+fn truncation_counts_display_width_and_keeps_chars_whole() {
+    let truncated = |max_length: &str| {
+        let server = StubServer::start(routes().respond(
+            "rest/events",
+            json!([folder_completion(1, LAPTOP, PHOTOS, 42., 3 * 1024 * 1024)]),
+        ));
+        let module = Module::spawn(
+            &server,
+            &["--icon", "同期e\u{301}", "--text-max-length", max_length],
+        );
+        module.next_status()["text"].as_str().unwrap().to_owned()
+    };
+
+    let wide = truncated("4");
+    assert_eq!(wide, "同…");
+    assert_eq!(wide.width(), 3);
+
+    let combining = truncated("6");
+    assert_eq!(combining, "同期e\u{301}…");
+    assert_eq!(combining.width(), 6);
+}
+
+#[test]
+fn global_progress_includes_synced_folders() {
+    const MIB: u64 = 1024 * 1024;
+    let server = StubServer::start(routes().respond(
+        "rest/events",
+        json!([
+            with_global_bytes(folder_completion(1, LAPTOP, PHOTOS, 50., 4 * MIB), 8 * MIB),
+            with_global_bytes(folder_completion(2, PHONE, PHOTOS, 100., 0), 8 * MIB),
+            with_global_bytes(folder_completion(3, PHONE, "music-id", 100., 0), 4 * MIB),
+        ]),
+    ));
+    let module = Module::spawn(&server, &["--show-global-progress"]);
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{f2f1} 80%/4 MiB");
+    assert_eq!(status["percentage"], 80);
+}
+
+#[test]
+fn require_tls_refuses_remote_plaintext() {
+    let output = run_with_args(&["--require-tls", "--base-url", "http://192.0.2.1:8384"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Refusing to send the API key"), "{stderr}");
+}
+
+#[test]
+fn require_tls_allows_loopback() {
+    let server = StubServer::start(routes().respond(
+        "rest/events",
+        json!([folder_completion(1, LAPTOP, PHOTOS, 42., 3 * 1024 * 1024)]),
+    ));
+    let module = Module::spawn(&server, &["--require-tls"]);
+
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 42%/3 MiB");
+}
+
+#[test]
+fn percentage_follows_the_overall_completion() {
+    const MIB: u64 = 1024 * 1024;
+    let server = StubServer::start(
+        routes()
+            .respond(
+                "rest/events",
+                json!([
+                    with_global_bytes(folder_completion(1, LAPTOP, PHOTOS, 50., 4 * MIB), 8 * MIB),
+                    with_global_bytes(folder_completion(2, PHONE, PHOTOS, 50., 2 * MIB), 4 * MIB),
+                ]),
+            )
+            .respond(
+                "rest/events",
+                json!([
+                    folder_completion(3, LAPTOP, PHOTOS, 100., 0),
+                    folder_completion(4, PHONE, PHOTOS, 100., 0),
+                ]),
+            ),
+    );
+    let module = Module::spawn(&server, &[]);
+
+    assert_eq!(module.next_status()["percentage"], 50);
+    assert!(module.next_status().get("percentage").is_none());
+}
+
+#[test]
+fn minimal_text_has_no_sizes() {
+    let server = StubServer::start(
+        routes()
+            .respond(
+                "rest/events",
+                json!([
+                    folder_completion(1, LAPTOP, PHOTOS, 20., 1024 * 1024),
+                    folder_completion(2, PHONE, PHOTOS, 40., 1024 * 1024),
+                ]),
+            )
+            .respond(
+                "rest/events",
+                json!([
+                    folder_completion(3, LAPTOP, PHOTOS, 100., 0),
+                    folder_completion(4, PHONE, PHOTOS, 100., 0),
+                ]),
+            ),
+    );
+    let module = Module::spawn(&server, &["--minimal"]);
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{f2f1} 30%");
+    assert_eq!(status["percentage"], 30);
+    assert_eq!(module.next_status()["text"], "");
+}
+
+#[test]
+fn disk_events_show_transferring_file() {
+    let server = StubServer::start(
+        routes()
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 42., 3 * 1024 * 1024)]),
+            )
+            .respond(
+                "rest/events/disk",
+                json!([{
+                    "id": 5,
+                    "type": "RemoteChangeDetected",
+                    "data": {
+                        "action": "added",
+                        "folder": PHOTOS,
+                        "folderID": PHOTOS,
+                        "label": "Photos",
+                        "modifiedBy": "PHONE",
+                        "path": "2024/photo.jpg",
+                        "type": "file",
+                    },
+                }]),
+            )
+            .respond("rest/events/disk", json!([])),
+    );
+    let module = Module::spawn(&server, &["--disk-events"]);
+
+    assert_eq!(
+        module.next_status()["tooltip"],
+        "<b>laptop</b>\n  Photos     42% (3 MiB)\ndownloading photo.jpg (Photos)"
+    );
+}
+
+#[test]
+fn disk_activity_is_forgotten_once_its_folder_is_synced() {
+    let server = StubServer::start(
+        routes()
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 42., 3 * 1024 * 1024)]),
+            )
+            .respond(
+                "rest/events",
+                json!([folder_completion(2, LAPTOP, PHOTOS, 100., 0)]),
+            )
+            .respond(
+                "rest/events",
+                json!([folder_completion(3, PHONE, PHOTOS, 60., 1024 * 1024)]),
+            )
+            .respond(
+                "rest/events/disk",
+                json!([{
+                    "id": 5,
+                    "type": "LocalChangeDetected",
+                    "data": {
+                        "action": "modified",
+                        "folder": PHOTOS,
+                        "folderID": PHOTOS,
+                        "label": "Photos",
+                        "path": "2024/photo.jpg",
+                        "type": "file",
+                    },
+                }]),
+            )
+            .respond("rest/events/disk", json!([])),
+    );
+    let module = Module::spawn(&server, &["--disk-events"]);
+
+    assert_eq!(
+        module.next_status()["tooltip"],
+        "<b>laptop</b>\n  Photos     42% (3 MiB)\nuploading photo.jpg (Photos)"
+    );
+    assert_eq!(module.next_status()["tooltip"], "");
+    assert_eq!(
+        module.next_status()["tooltip"],
+        "<b>phone</b>\n  Photos     60% (1 MiB)"
+    );
+}
+
+#[test]
+fn need_bytes_above_global_bytes_is_clamped() {
+    const MIB: u64 = 1024 * 1024;
+    let server = StubServer::start(routes().respond(
+        "rest/events",
+        json!([with_global_bytes(
+            folder_completion(1, LAPTOP, PHOTOS, -20., 12 * MIB),
+            8 * MIB
+        )]),
+    ));
+    let module = Module::spawn(&server, &["--progress-bar"]);
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{f2f1} 0%/8 MiB");
+    assert_eq!(status["percentage"], 0);
+    assert_eq!(status["tooltip"], "<b>laptop</b>\n  Photos     0% (8 MiB)");
+}
+
+#[test]
+fn config_saved_refreshes_names() {
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                config(&[(LAPTOP, "laptop")], &[(PHOTOS, "Photos")]),
+            )
+            .respond(
+                "rest/system/config",
+                config(&[(LAPTOP, "work laptop")], &[(PHOTOS, "Pictures")]),
+            )
+            .respond("rest/system/connections", connections(&[(LAPTOP, true)]))
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+            )
+            .respond(
+                "rest/events",
+                json!([{ "id": 2, "type": "ConfigSaved", "data": { "version": 37 } }]),
+            ),
+    );
+    let module = Module::spawn(&server, &["--refresh-on-config-changed"]);
+
+    assert_eq!(
+        module.next_status()["tooltip"],
+        "<b>laptop</b>\n  Photos     40% (1 MiB)"
+    );
+    assert_eq!(
+        module.next_status()["tooltip"],
+        "<b>work laptop</b>\n  Pictures   40% (1 MiB)"
+    );
+    assert!(server.requests().iter().any(|r| r.contains(
+        "events=FolderCompletion,DeviceDisconnected,DeviceConnected,FolderPaused,FolderErrors,ConfigSaved"
+    )));
+}
+
+#[test]
+fn legacy_syncthing_events_without_byte_counts() {
+    let server = StubServer::start(
+        Routes::new()
+            .respond("rest/system/status", system_status(STARTED))
+            .respond(
+                "rest/system/config",
+                config(&[(LAPTOP, "laptop")], &[(PHOTOS, "Photos")]),
+            )
+            .respond("rest/system/connections", connections(&[(LAPTOP, true)]))
+            .respond(
+                "rest/events",
+                json!([{
+                    "id": 1,
+                    "type": "FolderCompletion",
+                    "data": { "completion": 25, "device": LAPTOP, "folder": PHOTOS },
+                }]),
+            ),
+    );
+    let module = Module::spawn(&server, &[]);
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{f2f1} 25%");
+    assert_eq!(status["tooltip"], "<b>laptop</b>\n  Photos     25%");
+    assert!(!status.to_string().contains("0 B"), "{status}");
+}
+
+#[test]
+fn identical_percentages_are_collapsed() {
+    let server = StubServer::start(routes().respond(
+        "rest/events",
+        json!([
+            folder_completion(1, LAPTOP, PHOTOS, 50.2, 1024 * 1024),
+            folder_completion(2, LAPTOP, "music-id", 50.4, 1024 * 1024),
+            folder_completion(3, PHONE, PHOTOS, 50., 1024 * 1024),
+        ]),
+    ));
+    let module = Module::spawn(&server, &["--collapse-identical-percent"]);
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{f2f1} 3 folders at 50%");
+    assert_eq!(tooltip_entries(&status), 3);
+}
+
+#[test]
+fn max_segments_counts_the_rest() {
+    let server = StubServer::start(routes().respond(
+        "rest/events",
+        json!([
+            folder_completion(1, PHONE, PHOTOS, 30., 1024 * 1024),
+            folder_completion(2, LAPTOP, PHOTOS, 10., 1024 * 1024),
+            folder_completion(3, LAPTOP, "music-id", 20., 1024 * 1024),
+        ]),
+    ));
+    let module = Module::spawn(&server, &["--max-segments", "2"]);
+
+    let status = module.next_status();
+    assert_eq!(
+        status["text"],
+        "\u{f2f1} 20%/1 MiB | \u{f2f1} 10%/1 MiB | +1 more"
+    );
+    assert_eq!(tooltip_entries(&status), 3);
+}
+
+#[test]
+fn list_events_matches_subscription() {
+    let output = run_with_args(&["--list-events", "--refresh-on-config-changed"]);
+    assert!(output.status.success());
+
+    let listing = String::from_utf8_lossy(&output.stdout);
+    let subscribed = listing
+        .lines()
+        .filter(|line| line.contains(" subscribed "))
+        .filter_map(|line| line.split_whitespace().next())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        subscribed,
+        [
+            "FolderCompletion",
+            "DeviceDisconnected",
+            "DeviceConnected",
+            "FolderPaused",
+            "FolderErrors",
+            "ConfigSaved"
+        ]
+    );
+}
+
+#[test]
+fn raw_includes_tracked_progress_only_when_enabled() {
+    let events = json!([with_global_bytes(
+        folder_completion(1, LAPTOP, PHOTOS, 25., 1024),
+        4096
+    )]);
+
+    let server = StubServer::start(routes().respond("rest/events", events.clone()));
+    let module = Module::spawn(&server, &[]);
+    assert!(module.next_status().get("debug").is_none());
+
+    let server = StubServer::start(routes().respond("rest/events", events));
+    let module = Module::spawn(&server, &["--raw"]);
+    assert_eq!(
+        module.next_status()["debug"],
+        json!({ LAPTOP: { PHOTOS: { "completion": 25., "needBytes": 1024, "globalBytes": 4096 } } })
+    );
+}
+
+#[test]
+fn text_aggregates_folder_across_devices() {
+    let server = StubServer::start(routes().respond(
+        "rest/events",
+        json!([
+            folder_completion(1, LAPTOP, PHOTOS, 20., 1024 * 1024),
+            folder_completion(2, PHONE, PHOTOS, 40., 2 * 1024 * 1024),
+        ]),
+    ));
+    let module = Module::spawn(&server, &["--aggregate-across-devices-in-text"]);
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{f2f1} 30%/3 MiB");
+    assert_eq!(tooltip_entries(&status), 2);
+}
+
+#[test]
+fn syncthing_config_provides_url_base_and_api_key() {
+    let server = StubServer::start(
+        Routes::new()
+            .respond("syncthing/rest/system/status", system_status(STARTED))
+            .respond(
+                "syncthing/rest/system/config",
+                config(&[(LAPTOP, "laptop")], &[(PHOTOS, "Photos")]),
+            )
+            .respond(
+                "syncthing/rest/system/connections",
+                connections(&[(LAPTOP, true)]),
+            )
+            .respond(
+                "syncthing/rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 42., 3 * 1024 * 1024)]),
+            ),
+    );
+
+    let address = server.base_url().trim_start_matches("http://");
+    let config_xml =
+        std::env::temp_dir().join(format!("waybar-syncthing-{}.xml", std::process::id()));
+    fs::write(
+        &config_xml,
+        format!(
+            r#"<configuration version="37">
+    <gui enabled="true" tls="false" debugging="false">
+        <address>{address}</address>
+        <apikey>from-config-xml</apikey>
+        <urlBase>/syncthing/</urlBase>
+        <theme>default</theme>
+    </gui>
+</configuration>"#
+        ),
+    )
+    .unwrap();
+
+    let module = Module::spawn_with_args(&["--syncthing-config", config_xml.to_str().unwrap()]);
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 42%/3 MiB");
+    fs::remove_file(config_xml).unwrap();
+}
+
+#[test]
+fn pinned_devices_are_listed_first() {
+    let server = StubServer::start(routes().respond(
+        "rest/events",
+        json!([
+            folder_completion(1, LAPTOP, PHOTOS, 20., 1024 * 1024),
+            folder_completion(2, PHONE, PHOTOS, 40., 2 * 1024 * 1024),
+        ]),
+    ));
+    let module = Module::spawn(&server, &["--pin-device", PHONE]);
+
+    let status = module.next_status();
+    let tooltip = status["tooltip"].as_str().unwrap();
+    let devices: Vec<_> = tooltip
+        .lines()
+        .filter_map(|line| line.strip_prefix("<b>")?.strip_suffix("</b>"))
+        .collect();
+    assert_eq!(devices, ["phone", "laptop"]);
+}
+
+#[test]
+fn stale_progress_after_completion_is_ignored() {
+    let server = StubServer::start(
+        routes()
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 50., 1024 * 1024)]),
+            )
+            .respond(
+                "rest/events",
+                json!([folder_completion(2, LAPTOP, PHOTOS, 100., 0)]),
+            )
+            .respond(
+                "rest/events",
+                json!([folder_completion(3, LAPTOP, PHOTOS, 50., 1024 * 1024)]),
+            )
+            .respond(
+                "rest/events",
+                json!([folder_completion(4, LAPTOP, PHOTOS, 100., 0)]),
+            ),
+    );
+    let hook_log =
+        std::env::temp_dir().join(format!("waybar-syncthing-hook-{}", std::process::id()));
+    let hook = format!(
+        "echo \"$SYNCTHING_DEVICE $SYNCTHING_FOLDER\" >> {}",
+        hook_log.display()
+    );
+    let module = Module::spawn(&server, &["--on-complete", &hook]);
+
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 50%/1 MiB");
+    let empty = json!({ "text": "", "tooltip": "", "class": "idle" });
+    assert_eq!(module.next_status(), empty);
+    server.wait_for_requests(1, |r| r.starts_with("/rest/events?since=4&"));
+    assert!(!module.prints_within(Duration::from_millis(200)));
+    assert_eq!(fs::read_to_string(&hook_log).unwrap(), "laptop Photos\n");
+    fs::remove_file(hook_log).unwrap();
+}
+
+#[test]
+fn notify_sends_one_notification_per_completion() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let server = StubServer::start(
+        routes()
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 50., 1024 * 1024)]),
+            )
+            .respond(
+                "rest/events",
+                json!([folder_completion(2, LAPTOP, PHOTOS, 100., 0)]),
+            )
+            .respond(
+                "rest/events",
+                json!([folder_completion(3, LAPTOP, PHOTOS, 99., 1024)]),
+            )
+            .respond(
+                "rest/events",
+                json!([folder_completion(4, LAPTOP, PHOTOS, 100., 0)]),
+            ),
+    );
+    let bin = std::env::temp_dir().join(format!("waybar-syncthing-notify-{}", std::process::id()));
+    fs::create_dir_all(&bin).unwrap();
+    let log = bin.join("log");
+    let script = bin.join("notify-send");
+    fs::write(
+        &script,
+        format!("#!/bin/sh\necho \"$3|$4\" >> {}\n", log.display()),
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!("{}:/usr/bin:/bin", bin.display());
+    let module = Module::spawn_with_env(
+        &[
+            "--api-key",
+            "stub-api-key",
+            "--base-url",
+            server.base_url(),
+            "--notify",
+        ],
+        &[("PATH", std::path::Path::new(&path))],
+    );
+
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 50%/1 MiB");
+    let empty = json!({ "text": "", "tooltip": "", "class": "idle" });
+    assert_eq!(module.next_status(), empty);
+    // Falling back to 99% right after completing is stale progress.
+    server.wait_for_requests(1, |r| r.starts_with("/rest/events?since=4&"));
+    assert!(!module.prints_within(Duration::from_millis(200)));
+    assert_eq!(
+        fs::read_to_string(&log).unwrap(),
+        "Photos is in sync|laptop finished syncing Photos\n"
+    );
+    fs::remove_dir_all(bin).unwrap();
+}
+
+#[test]
+fn reconnected_device_progress_is_polled() {
+    let shared = json!([{ "deviceID": "LOCAL-ID" }, { "deviceID": LAPTOP }]);
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                json!({
+                    "devices": [{ "deviceID": LAPTOP, "name": "laptop" }],
+                    "folders": [{ "id": PHOTOS, "label": "Photos", "devices": shared }],
+                }),
+            )
+            .respond("rest/system/connections", connections(&[(LAPTOP, true)]))
+            .respond(
+                "rest/db/completion",
+                json!({ "completion": 40., "needBytes": 1024 * 1024, "globalBytes": 2 * 1024 * 1024 }),
+            )
+            .respond("rest/events", json!([device_connected(1, LAPTOP)])),
+    );
+    let module = Module::spawn(&server, &[]);
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{f2f1} 40%/1 MiB");
+    assert_eq!(status["tooltip"], "<b>laptop</b>\n  Photos     40% (1 MiB)");
+    assert!(server
+        .requests()
+        .iter()
+        .any(|r| r == &format!("/rest/db/completion?device={LAPTOP}&folder={PHOTOS}")));
+}
+
+#[test]
+fn bootstrap_respects_max_concurrent_requests() {
+    let shared = json!([{ "deviceID": "LOCAL-ID" }, { "deviceID": LAPTOP }, { "deviceID": PHONE }]);
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                json!({
+                    "devices": [
+                        { "deviceID": LAPTOP, "name": "laptop" },
+                        { "deviceID": PHONE, "name": "phone" },
+                    ],
+                    "folders": [
+                        { "id": PHOTOS, "label": "Photos", "devices": shared },
+                        { "id": "music-id", "label": "Music", "devices": shared },
+                        { "id": "docs-id", "label": "Docs", "devices": shared },
+                    ],
+                }),
+            )
+            .respond(
+                "rest/system/connections",
+                connections(&[(LAPTOP, true), (PHONE, true)]),
+            )
+            .respond_after(
+                "rest/db/completion",
+                Duration::from_millis(200),
+                json!({ "completion": 50., "needBytes": 1024 * 1024, "globalBytes": 2 * 1024 * 1024 }),
+            )
+            .respond("rest/events", json!([])),
+    );
+    let module = Module::spawn(
+        &server,
+        &["--bootstrap-completion", "--max-concurrent-requests", "2"],
+    );
+
+    let status = module.next_status();
+    assert_eq!(tooltip_entries(&status), 6);
+    assert_eq!(server.peak_concurrency("rest/db/completion"), 2);
+    assert!(!server
+        .requests()
+        .iter()
+        .any(|r| r.contains("device=LOCAL-ID")));
+}
+
+#[test]
+fn env_file_provides_base_url_and_api_key() {
+    let server = StubServer::start(routes().respond(
+        "rest/events",
+        json!([folder_completion(1, LAPTOP, PHOTOS, 42., 3 * 1024 * 1024)]),
+    ));
+
+    let env_file =
+        std::env::temp_dir().join(format!("waybar-syncthing-{}.env", std::process::id()));
+    fs::write(
+        &env_file,
+        format!(
+            "# waybar-syncthing\nSYNCTHING_API_KEY=stub-api-key\nexport SYNCTHING_BASE_URL=\"{}\"\n",
+            server.base_url()
+        ),
+    )
+    .unwrap();
+
+    let module = Module::spawn_with_args(&["--env-file", env_file.to_str().unwrap()]);
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 42%/3 MiB");
+    fs::remove_file(env_file).unwrap();
+}
+
+#[test]
+fn missing_connections_endpoint_is_not_fatal() {
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                config(&[(LAPTOP, "laptop")], &[(PHOTOS, "Photos")]),
+            )
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 42., 3 * 1024 * 1024)]),
+            )
+            .respond(
+                "rest/events",
+                json!([folder_completion(2, LAPTOP, PHOTOS, 60., 2 * 1024 * 1024)]),
+            ),
+    );
+    let module = Module::spawn(&server, &[]);
+
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 42%/3 MiB");
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 60%/2 MiB");
+}
+
+#[test]
+fn bucketed_text_counts_folders_by_activity() {
+    let server = StubServer::start(
+        routes()
+            .respond("rest/db/status", json!({ "state": "idle" }))
+            .respond(
+                "rest/events",
+                json!([
+                    state_changed(1, PHOTOS, "syncing"),
+                    state_changed(2, "music-id", "syncing"),
+                    state_changed(3, "docs-id", "scanning"),
+                    state_changed(4, "books-id", "scanning"),
+                    state_changed(5, "books-id", "idle"),
+                    folder_completion(6, LAPTOP, PHOTOS, 20., 1024 * 1024),
+                    folder_completion(7, PHONE, PHOTOS, 40., 1024 * 1024),
+                ]),
+            ),
+    );
+    let module = Module::spawn(&server, &["--bucketed-text"]);
+
+    assert_eq!(
+        module.next_status()["text"],
+        "\u{2b07}2 \u{2b06}1 \u{27f3}1"
+    );
+    assert!(server.requests().iter().any(|r| r.contains("StateChanged")));
+}
+
+#[test]
+fn bucketed_text_counts_folders_already_syncing_at_startup() {
+    let server = StubServer::start(
+        routes()
+            .respond("rest/db/status", json!({ "state": "syncing" }))
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, PHONE, PHOTOS, 40., 1024 * 1024)]),
+            ),
+    );
+    let module = Module::spawn(&server, &["--bucketed-text"]);
+
+    assert_eq!(module.next_status()["text"], "\u{2b07}1 \u{2b06}1");
+    assert!(server
+        .requests()
+        .iter()
+        .any(|r| r.contains(&format!("rest/db/status?folder={PHOTOS}"))));
+}
+
+#[test]
+fn completion_cache_skips_recently_synced_folders() {
+    let shared = json!([{ "deviceID": LAPTOP }, { "deviceID": PHONE }]);
+    let server = StubServer::start(
+        Routes::new()
+            .respond("rest/system/status", system_status(STARTED))
+            .respond(
+                "rest/system/status",
+                system_status("2024-01-02T10:00:00+01:00"),
+            )
+            .respond(
+                "rest/system/status",
+                system_status("2024-01-03T10:00:00+01:00"),
+            )
+            .respond(
+                "rest/system/config",
+                json!({
+                    "devices": [
+                        { "deviceID": LAPTOP, "name": "laptop" },
+                        { "deviceID": PHONE, "name": "phone" },
+                    ],
+                    "folders": [{ "id": PHOTOS, "label": "Photos", "devices": shared }],
+                }),
+            )
+            .respond(
+                "rest/system/connections",
+                connections(&[(LAPTOP, true), (PHONE, true)]),
+            )
+            .respond(
+                "rest/db/completion",
+                json!({ "completion": 100., "needBytes": 0, "globalBytes": 1024 }),
+            )
+            .respond("rest/events", json!([]))
+            .drop_connection("rest/events")
+            .respond("rest/events", json!([]))
+            .drop_connection_after("rest/events", Duration::from_millis(1500))
+            .respond("rest/events", json!([])),
+    );
+    let module = Module::spawn(
+        &server,
+        &["--bootstrap-completion", "--completion-cache-ttl", "2"],
+    );
+    let completion_requests = || {
+        server
+            .requests()
+            .iter()
+            .filter(|r| r.starts_with("/rest/db/completion"))
+            .count()
+    };
+
+    module.next_status();
+    assert_eq!(completion_requests(), 2);
+
+    // Restarted within the TTL: both folders were just seen at 100%.
+    assert_eq!(module.next_status()["class"], "disconnected");
+    module.next_status();
+    assert_eq!(completion_requests(), 2);
+
+    // Restarted again after the TTL ran out.
+    assert_eq!(module.next_status()["class"], "disconnected");
+    module.next_status();
+    assert_eq!(completion_requests(), 4);
+}
+
+#[test]
+fn device_with_any_live_connection_counts_as_connected() {
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                config(
+                    &[(LAPTOP, "laptop"), (PHONE, "phone")],
+                    &[(PHOTOS, "Photos")],
+                ),
+            )
+            .respond(
+                "rest/system/connections",
+                json!({
+                    "connections": {
+                        LAPTOP: [{ "connected": false }, { "connected": true }],
+                        PHONE: [{ "connected": false }, { "connected": false }],
+                    },
+                }),
+            )
+            .respond(
+                "rest/events",
+                json!([
+                    folder_completion(1, LAPTOP, PHOTOS, 10., 1024 * 1024),
+                    folder_completion(2, PHONE, PHOTOS, 20., 1024 * 1024),
+                ]),
+            ),
+    );
+    let module = Module::spawn(&server, &[]);
+
+    assert_eq!(
+        module.next_status()["tooltip"],
+        "<b>laptop</b>\n  Photos     10% (1 MiB)"
+    );
+}
+
+#[test]
+fn disconnected_text_is_shown_when_syncthing_is_down() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+
+    let module = Module::spawn_with_args(&[
+        "--api-key",
+        "stub-api-key",
+        "--base-url",
+        &base_url,
+        "--disconnected-text",
+        "\u{26a0} syncthing down",
+    ]);
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{26a0} syncthing down");
+    assert_eq!(status["class"], "disconnected");
+}
+
+#[test]
+fn renamed_folder_keeps_pending_progress() {
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                config(&[(LAPTOP, "laptop")], &[(PHOTOS, "Photos")]),
+            )
+            .respond(
+                "rest/system/config",
+                config(
+                    &[(LAPTOP, "laptop")],
+                    &[(PHOTOS, "Pictures"), ("music-id", "Music")],
+                ),
+            )
+            .respond("rest/system/connections", connections(&[(LAPTOP, true)]))
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+            )
+            .respond(
+                "rest/events",
+                json!([folder_completion(2, LAPTOP, "music-id", 10., 1024 * 1024)]),
+            ),
+    );
+    let module = Module::spawn(&server, &[]);
+
+    assert_eq!(
+        module.next_status()["tooltip"],
+        "<b>laptop</b>\n  Photos     40% (1 MiB)"
+    );
+    // Loading the unknown folder also picks up the new label of the pending one.
+    assert_eq!(
+        module.next_status()["tooltip"],
+        "<b>laptop</b>\n  Music      10% (1 MiB)\n  Pictures   40% (1 MiB)"
+    );
+}
+
+#[test]
+fn strict_completion_mode_counts_pending_deletes() {
+    let mut event = folder_completion(1, LAPTOP, PHOTOS, 100., 0);
+    event["data"]["globalItems"] = 10.into();
+    event["data"]["needItems"] = 0.into();
+    event["data"]["needDeletes"] = 2.into();
+    let server = || StubServer::start(routes().respond("rest/events", json!([event])));
+
+    let sync = server();
+    let module = Module::spawn(&sync, &["--completion-mode", "sync"]);
+    assert_eq!(
+        module.next_status(),
+        json!({ "text": "", "tooltip": "", "class": "idle" })
+    );
+
+    let strict = server();
+    let module = Module::spawn(&strict, &["--completion-mode", "strict"]);
+    assert_eq!(
+        module.next_status()["tooltip"],
+        "<b>laptop</b>\n  Photos     83% (metadata)"
+    );
+}
+
+#[test]
+fn print_on_signal_only_prints_when_asked() {
+    let server = StubServer::start(routes().respond(
+        "rest/events",
+        json!([folder_completion(1, LAPTOP, PHOTOS, 42., 3 * 1024 * 1024)]),
+    ));
+    let mut module = Module::spawn(&server, &["--print-on-signal"]);
+
+    assert!(!module.prints_within(Duration::from_millis(500)));
+    module.send_line("");
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 42%/3 MiB");
+}
+
+#[test]
+fn print_on_signal_clears_plain_output_once_synced() {
+    let server = StubServer::start(
+        routes()
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 42., 3 * 1024 * 1024)]),
+            )
+            .respond_after(
+                "rest/events",
+                Duration::from_secs(1),
+                json!([folder_completion(2, LAPTOP, PHOTOS, 100., 0)]),
+            ),
+    );
+    let mut module = Module::spawn(&server, &["--print-on-signal", "--output-format", "plain"]);
+    let events = |r: &str| r.starts_with("/rest/events?");
+
+    server.wait_for_requests(2, events);
+    module.send_line("");
+    assert_eq!(module.next_line(), "\u{f2f1} 42%/3 MiB");
+
+    server.wait_for_requests(3, events);
+    module.send_line("");
+    assert_eq!(module.next_line(), "");
+}
+
+#[test]
+fn errors_name_the_failing_endpoint() {
+    let server = StubServer::start(routes().respond_with("rest/events", 403, "forbidden"));
+
+    let output = run(&server, &[]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Failed to read rest/events since 0"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn silent_event_stream_falls_back_to_polling_completion() {
+    let server = StubServer::start(
+        routes()
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 40., 2 * 1024 * 1024)]),
+            )
+            .respond(
+                "rest/db/completion",
+                json!({ "completion": 70., "needBytes": 1024 * 1024, "globalBytes": 4 * 1024 * 1024 }),
+            ),
+    );
+    let module = Module::spawn(&server, &["--completion-poll-fallback", "1"]);
+
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 40%/2 MiB");
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 70%/1 MiB");
+    assert!(server
+        .requests()
+        .iter()
+        .any(|r| r == "/rest/db/completion?device=LAPTOP-ID&folder=photos-id"));
+}
+
+#[test]
+fn tooltip_columns_align_by_display_width() {
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                config(&[(LAPTOP, "笔记本")], &[(PHOTOS, "照片")]),
+            )
+            .respond("rest/system/connections", connections(&[(LAPTOP, true)]))
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 42., 3 * 1024 * 1024)]),
+            ),
+    );
+    let module = Module::spawn(&server, &[]);
+
+    // Each CJK glyph takes two columns: "笔记本:" is 7 wide, "照片" is 4 wide.
+    assert_eq!(
+        module.next_status()["tooltip"],
+        "<b>笔记本</b>\n  照片       42% (3 MiB)"
+    );
+}
+
+#[test]
+fn poll_events_max_wait_sets_long_poll_timeout() {
+    let server = StubServer::start(routes().respond(
+        "rest/events",
+        json!([folder_completion(1, LAPTOP, PHOTOS, 42., 3 * 1024 * 1024)]),
+    ));
+    let module = Module::spawn(&server, &["--poll-events-max-wait", "1"]);
+
+    module.next_status();
+    // One idle long-poll returns after a second and the next one starts.
+    server.wait_for_requests(3, |r| r.starts_with("/rest/events?"));
+    let requests = server.requests();
+    let events = requests
+        .iter()
+        .filter(|r| r.starts_with("/rest/events?"))
+        .collect::<Vec<_>>();
+    assert!(
+        events.iter().all(|r| r.ends_with("&timeout=1")),
+        "{events:?}"
+    );
+}
+
+#[test]
+fn metadata_only_sync_is_labelled() {
+    let mut event = folder_completion(1, LAPTOP, PHOTOS, 99., 0);
+    event["data"]["needItems"] = 3.into();
+    let server = StubServer::start(routes().respond("rest/events", json!([event])));
+    let module = Module::spawn(&server, &[]);
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{f2f1} 99%/metadata");
+    assert_eq!(
+        status["tooltip"],
+        "<b>laptop</b>\n  Photos     99% (metadata)"
+    );
+}
+
+#[test]
+fn metadata_only_sync_is_labelled_in_the_progress_bar() {
+    let mut event = folder_completion(1, LAPTOP, PHOTOS, 99., 0);
+    event["data"]["needItems"] = 3.into();
+    let server = StubServer::start(routes().respond("rest/events", json!([event])));
+    let module = Module::spawn(&server, &["--progress-bar"]);
+
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 99%/metadata");
+}
+
+#[test]
+fn metadata_only_local_sync_is_labelled() {
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                config(&[("LOCAL-ID", "desktop"), (LAPTOP, "laptop")], &[]),
+            )
+            .respond(
+                "rest/db/completion",
+                json!({ "completion": 99., "needBytes": 0, "needItems": 3, "globalBytes": 20 * 1024 * 1024 }),
+            ),
+    );
+    let module = Module::spawn(&server, &["--mode", "local"]);
+
+    assert_eq!(
+        module.next_status()["tooltip"],
+        "<b>desktop</b>\n  All folders 99% (metadata)"
+    );
+}
+
+#[test]
+fn metadata_only_local_pull_is_labelled() {
+    let mut summary = folder_summary(1, PHOTOS, "syncing", 0);
+    summary["data"]["summary"]["needTotalItems"] = 3.into();
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                config(
+                    &[("LOCAL-ID", "desktop"), (LAPTOP, "laptop")],
+                    &[(PHOTOS, "Photos")],
+                ),
+            )
+            .respond("rest/system/connections", connections(&[(LAPTOP, true)]))
+            .respond("rest/events", json!([summary])),
+    );
+    let module = Module::spawn(&server, &["--local-progress"]);
+
+    assert_eq!(
+        module.next_status()["tooltip"],
+        "<b>desktop</b>\n  Photos     100% (metadata)"
+    );
+}
+
+#[test]
+fn hang_timeout_exits_when_nothing_succeeds() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+
+    let output = run_with_args(&["--base-url", &base_url, "--hang-timeout", "1"]);
+    assert_eq!(output.status.code(), Some(3));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("No request to Syncthing succeeded for 1s"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn synced_ratio_counts_devices_without_pending_folders() {
+    let server = StubServer::start(routes().respond(
+        "rest/events",
+        json!([folder_completion(1, LAPTOP, PHOTOS, 42., 3 * 1024 * 1024)]),
+    ));
+    let module = Module::spawn(&server, &["--show-synced-ratio"]);
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{f2f1} 42%/3 MiB | 1/2 synced");
+    assert_eq!(
+        status["tooltip"],
+        "<b>laptop</b>\n  Photos     42% (3 MiB)\n1/2 devices synced"
+    );
+}
+
+#[test]
+fn folder_threshold_overrides_completion_threshold() {
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                config(
+                    &[(LAPTOP, "laptop")],
+                    &[(PHOTOS, "Photos"), ("music-id", "Music")],
+                ),
+            )
+            .respond("rest/system/connections", connections(&[(LAPTOP, true)]))
+            .respond(
+                "rest/events",
+                json!([
+                    folder_completion(1, LAPTOP, PHOTOS, 96., 1024 * 1024),
+                    folder_completion(2, LAPTOP, "music-id", 96., 1024 * 1024),
+                ]),
+            ),
+    );
+    let module = Module::spawn(&server, &["--folder-threshold", "photos-id=95"]);
+
+    assert_eq!(
+        module.next_status()["tooltip"],
+        "<b>laptop</b>\n  Music      96% (1 MiB)"
+    );
+}
+
+#[test]
+fn thresholds_have_to_be_percentages() {
+    for args in [
+        &["--completion-threshold", "120"][..],
+        &["--completion-threshold=-1"],
+        &["--folder-threshold", "photos-id=101"],
+    ] {
+        let output = run_with_args(args);
+        assert!(!output.status.success(), "{args:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("between 0 and 100"), "{stderr}");
+    }
+}
+
+#[test]
+fn json_full_output_lists_pending_entries() {
+    let server = StubServer::start(routes().respond(
+        "rest/events",
+        json!([with_global_bytes(
+            folder_completion(1, LAPTOP, PHOTOS, 50., 2 * 1024 * 1024),
+            4 * 1024 * 1024
+        )]),
+    ));
+    let module = Module::spawn(&server, &["--output-format", "json-full"]);
+
+    assert_eq!(
+        module.next_status(),
+        json!({
+            "text": "\u{f2f1} 50%/2 MiB",
+            "tooltip": "<b>laptop</b>\n  Photos     50% (2 MiB)",
+            "class": "syncing",
+            "pending": [{
+                "device": LAPTOP,
+                "deviceName": "laptop",
+                "folder": PHOTOS,
+                "folderName": "Photos",
+                "completion": 50.,
+                "needBytes": 2 * 1024 * 1024,
+                "globalBytes": 4 * 1024 * 1024,
+            }],
+            "aggregate": {
+                "completion": 50.,
+                "needBytes": 2 * 1024 * 1024,
+                "globalBytes": 4 * 1024 * 1024,
+            },
+        })
+    );
+}
+
+#[test]
+fn nested_completion_object_is_understood() {
+    let shared = json!([{ "deviceID": LAPTOP }]);
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                json!({
+                    "devices": [{ "deviceID": LAPTOP, "name": "laptop" }],
+                    "folders": [{ "id": PHOTOS, "label": "Photos", "devices": shared }],
+                }),
+            )
+            .respond("rest/system/connections", connections(&[(LAPTOP, true)]))
+            .respond(
+                "rest/db/completion",
+                json!({
+                    "completion": {
+                        "completion": 25.,
+                        "needBytes": 3 * 1024 * 1024,
+                        "globalBytes": 4 * 1024 * 1024,
+                        "needItems": 7,
+                    },
+                }),
+            )
+            .respond("rest/events", json!([])),
+    );
+    let module = Module::spawn(&server, &["--bootstrap-completion"]);
+
+    assert_eq!(
+        module.next_status()["tooltip"],
+        "<b>laptop</b>\n  Photos     25% (3 MiB)"
+    );
+}
+
+#[test]
+fn globally_paused_syncthing_is_shown() {
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                json!({
+                    "devices": [
+                        { "deviceID": LAPTOP, "name": "laptop", "paused": true },
+                        { "deviceID": PHONE, "name": "phone", "paused": true },
+                    ],
+                    "folders": [{ "id": PHOTOS, "label": "Photos" }],
+                }),
+            )
+            .respond(
+                "rest/system/connections",
+                connections(&[(LAPTOP, true), (PHONE, true)]),
+            )
+            .respond("rest/events", json!([]))
+            .respond(
+                "rest/events",
+                json!([
+                    { "id": 1, "type": "DeviceResumed", "data": { "device": LAPTOP } },
+                    folder_completion(2, LAPTOP, PHOTOS, 40., 1024 * 1024),
+                ]),
+            ),
+    );
+    let module = Module::spawn(&server, &["--show-when-paused-globally"]);
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "syncthing paused");
+    assert_eq!(status["class"], "paused");
+
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 40%/1 MiB");
+}
+
+#[test]
+fn byte_figures_share_one_formatter() {
+    const MIB: u64 = 1024 * 1024;
+    const TIB: u64 = 1024 * 1024 * 1024 * 1024;
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                config(
+                    &[(LAPTOP, "laptop")],
+                    &[
+                        (PHOTOS, "Photos"),
+                        ("music-id", "Music"),
+                        ("docs-id", "Docs"),
+                    ],
+                ),
+            )
+            .respond("rest/system/connections", connections(&[(LAPTOP, true)]))
+            .respond(
+                "rest/events",
+                json!([
+                    folder_completion(1, LAPTOP, PHOTOS, 10., 3 * TIB / 2),
+                    folder_completion(2, LAPTOP, "music-id", 20., MIB / 2),
+                    folder_completion(3, LAPTOP, "docs-id", 30., 5 * MIB),
+                ]),
+            ),
+    );
+    let module = Module::spawn(
+        &server,
+        &[
+            "--tooltip-bytes",
+            "full",
+            "--text-bytes",
+            "short",
+            "--per-folder-text",
+        ],
+    );
+
+    let status = module.next_status();
+    assert_eq!(
+        status["tooltip"],
+        "<b>laptop</b>\n  Docs       30% (5 MiB)\n  Music      20% (512 KiB)\n  Photos     10% (1.50 TiB)"
+    );
+    assert_eq!(
+        status["text"],
+        "\u{f2f1} 30%/5M | \u{f2f1} 20%/512K | \u{f2f1} 10%/2T"
+    );
+}
+
+#[test]
+fn text_rolls_up_every_folder_unless_asked_per_folder() {
+    let events = json!([
+        folder_completion(1, LAPTOP, PHOTOS, 20., 3 * 1024 * 1024),
+        folder_completion(2, PHONE, PHOTOS, 60., 1024 * 1024),
+    ]);
+    let rolled_up = StubServer::start(routes().respond("rest/events", events.clone()));
+    let per_folder = StubServer::start(routes().respond("rest/events", events));
+
+    let status = Module::spawn(&rolled_up, &[]).next_status();
+    assert_eq!(status["text"], "\u{f2f1} 40%/4 MiB");
+    assert_eq!(
+        status["tooltip"],
+        "<b>laptop</b>\n  Photos     20% (3 MiB)\n<b>phone</b>\n  Photos     60% (1 MiB)"
+    );
+    assert_eq!(
+        Module::spawn(&per_folder, &["--per-folder-text"]).next_status()["text"],
+        "\u{f2f1} 20%/3 MiB | \u{f2f1} 60%/1 MiB"
+    );
+}
+
+#[test]
+fn class_follows_sync_state() {
+    let server = StubServer::start(
+        routes()
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+            )
+            .respond_with("rest/events", 500, "internal error")
+            .respond(
+                "rest/events",
+                json!([folder_completion(2, LAPTOP, PHOTOS, 100., 0)]),
+            ),
+    );
+    let module = Module::spawn(&server, &[]);
+
+    assert_eq!(module.next_status()["class"], "syncing");
+    assert_eq!(module.next_status()["class"], "error");
+    assert_eq!(module.next_status()["class"], "idle");
+}
+
+#[test]
+fn once_prints_a_single_status_and_exits() {
+    let shared = json!([{ "deviceID": "LOCAL-ID" }, { "deviceID": LAPTOP }]);
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                json!({
+                    "devices": [{ "deviceID": LAPTOP, "name": "laptop" }],
+                    "folders": [{ "id": PHOTOS, "label": "Photos", "devices": shared }],
+                }),
+            )
+            .respond("rest/system/connections", connections(&[(LAPTOP, true)]))
+            .respond(
+                "rest/db/completion",
+                json!({ "completion": 75., "needBytes": 1024 * 1024, "globalBytes": 4 * 1024 * 1024 }),
+            ),
+    );
+
+    let output = run(&server, &["--once"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 1, "{stdout}");
+    let status: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+    assert_eq!(status["text"], "\u{f2f1} 75%/1 MiB");
+    assert!(!server
+        .requests()
+        .iter()
+        .any(|r| r.starts_with("/rest/events")));
+}
+
+#[test]
+fn every_output_reports_the_same_aggregate() {
+    const MIB: u64 = 1024 * 1024;
+    let spawn = |args: &[&str]| {
+        let server = StubServer::start(routes().respond(
+            "rest/events",
+            json!([
+                with_global_bytes(folder_completion(1, LAPTOP, PHOTOS, 50., 2 * MIB), 4 * MIB),
+                with_global_bytes(folder_completion(2, PHONE, "music-id", 90., MIB), 2 * MIB),
+            ]),
+        ));
+        Module::spawn(&server, args).next_status()
+    };
+
+    let progress_bar = spawn(&["--progress-bar"]);
+    let minimal = spawn(&["--minimal"]);
+    let full = spawn(&["--progress-bar", "--output-format", "json-full"]);
+
+    assert_eq!(progress_bar["text"], "\u{f2f1} 50%/3 MiB");
+    assert_eq!(progress_bar["percentage"], 50);
+    assert_eq!(minimal["text"], "\u{f2f1} 50%");
+    assert_eq!(full["text"], progress_bar["text"]);
+    assert_eq!(
+        full["aggregate"],
+        json!({ "completion": 50., "needBytes": 3 * MIB, "globalBytes": 6 * MIB })
+    );
+}
+
+#[test]
+fn device_groups_section_the_tooltip() {
+    const NAS: &str = "NAS-ID";
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                config(
+                    &[(LAPTOP, "laptop"), (PHONE, "phone"), (NAS, "nas")],
+                    &[(PHOTOS, "Photos")],
+                ),
+            )
+            .respond(
+                "rest/system/connections",
+                connections(&[(LAPTOP, true), (PHONE, true), (NAS, true)]),
+            )
+            .respond(
+                "rest/events",
+                json!([
+                    folder_completion(1, LAPTOP, PHOTOS, 10., 1024 * 1024),
+                    folder_completion(2, NAS, PHOTOS, 20., 1024 * 1024),
+                    folder_completion(3, PHONE, PHOTOS, 30., 1024 * 1024),
+                ]),
+            ),
+    );
+    let module = Module::spawn(
+        &server,
+        &[
+            "--device-group",
+            "phones=PHONE-ID",
+            "--device-group",
+            "servers=NAS-ID",
+        ],
+    );
+
+    assert_eq!(
+        module.next_status()["tooltip"],
+        "<b>phones</b>\n  <b>phone</b>\n    Photos     30% (1 MiB)\n\
+         <b>servers</b>\n  <b>nas</b>\n    Photos     20% (1 MiB)\n\
+         <b>Other</b>\n  <b>laptop</b>\n    Photos     10% (1 MiB)"
+    );
+}
+
+#[test]
+fn retries_back_off_while_syncthing_is_down() {
+    let server = StubServer::start(
+        Routes::new()
+            .drop_connection("rest/system/status")
+            .drop_connection("rest/system/status")
+            .drop_connection("rest/system/status")
+            .respond("rest/system/status", system_status(STARTED))
+            .respond(
+                "rest/system/config",
+                config(&[(LAPTOP, "laptop")], &[(PHOTOS, "Photos")]),
+            )
+            .respond("rest/system/connections", connections(&[(LAPTOP, true)]))
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+            ),
+    );
+    let module = Module::spawn(&server, &[]);
+
+    assert_eq!(module.next_status()["class"], "disconnected");
+    let started = std::time::Instant::now();
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 40%/1 MiB");
+    // Waiting 1s, 2s and 4s, rather than 1s after each failure.
+    assert!(started.elapsed() >= Duration::from_secs(6));
+}
+
+#[test]
+fn malformed_events_are_skipped() {
+    let server = StubServer::start(
+        routes()
+            .respond_with("rest/events", 200, "[{\"id\": \"one\"}]")
+            .respond(
+                "rest/events",
+                json!([folder_completion(2, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+            ),
+    );
+    let module = Module::spawn(&server, &[]);
+
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 40%/1 MiB");
+}
+
+#[test]
+fn always_show_icon_keeps_module_hoverable() {
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                config(&[("LOCAL-ID", "desktop")], &[(PHOTOS, "Photos")]),
+            )
+            .respond("rest/system/connections", connections(&[]))
+            .respond("rest/db/status", json!({ "state": "idle" }))
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, "LOCAL-ID", PHOTOS, 40., 1024 * 1024)]),
+            ),
+    );
+    let module = Module::spawn(&server, &["--bucketed-text", "--always-show-icon"]);
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{f2f1}");
+    assert_eq!(
+        status["tooltip"],
+        "<b>desktop</b>\n  Photos     40% (1 MiB)"
+    );
+}
+
+#[test]
+fn event_ids_going_back_reset_pending() {
+    let server = StubServer::start(
+        routes()
+            .respond(
+                "rest/events",
+                json!([folder_completion(7, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+            )
+            .respond(
+                "rest/events",
+                json!([folder_completion(2, PHONE, PHOTOS, 50., 1024 * 1024)]),
+            )
+            .respond("rest/events", json!([])),
+    );
+    let module = Module::spawn(&server, &[]);
+
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 40%/1 MiB");
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 50%/1 MiB");
+    server.wait_for_requests(1, |r| r.starts_with("/rest/events?since=2&"));
+}
+
+#[test]
+fn unreadable_ca_cert_is_reported() {
+    let output = run_with_args(&["--ca-cert", "/nonexistent/ca.pem"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Failed to read CA certificate /nonexistent/ca.pem"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn progress_bar_keeps_per_folder_tooltip() {
+    const MIB: u64 = 1024 * 1024;
+    let server = StubServer::start(routes().respond(
+        "rest/events",
+        json!([
+            with_global_bytes(folder_completion(1, LAPTOP, PHOTOS, 75., MIB), 4 * MIB),
+            with_global_bytes(folder_completion(2, PHONE, PHOTOS, 50., 2 * MIB), 4 * MIB),
+        ]),
+    ));
+    let module = Module::spawn(&server, &["--progress-bar"]);
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{f2f1} 63%/3 MiB");
+    assert_eq!(
+        status["tooltip"],
+        "<b>laptop</b>\n  Photos     75% (1 MiB)\n<b>phone</b>\n  Photos     50% (2 MiB)"
+    );
+}
+
+#[test]
+fn small_transfers_show_kib_and_bytes() {
+    let server = StubServer::start(routes().respond(
+        "rest/events",
+        json!([
+            folder_completion(1, LAPTOP, PHOTOS, 99., 4 * 1024),
+            folder_completion(2, PHONE, PHOTOS, 99., 700),
+        ]),
+    ));
+    let module = Module::spawn(&server, &[]);
+
+    assert_eq!(
+        module.next_status()["tooltip"],
+        "<b>laptop</b>\n  Photos     99% (4 KiB)\n<b>phone</b>\n  Photos     99% (700 B)"
+    );
+}
+
+#[test]
+fn si_units_use_powers_of_1000() {
+    let server = StubServer::start(routes().respond(
+        "rest/events",
+        json!([folder_completion(1, LAPTOP, PHOTOS, 40., 2_500_000)]),
+    ));
+    let module = Module::spawn(&server, &["--si-units"]);
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{f2f1} 40%/2.50 MB");
+    assert_eq!(
+        status["tooltip"],
+        "<b>laptop</b>\n  Photos     40% (2.50 MB)"
+    );
+}
+
+#[test]
+fn show_rate_adds_speed_and_eta_to_tooltip() {
+    let counters = |total: u64| {
+        json!({ "connections": {
+            LAPTOP: { "connected": true, "inBytesTotal": 0, "outBytesTotal": total },
+        }})
+    };
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                config(&[(LAPTOP, "laptop")], &[(PHOTOS, "Photos")]),
+            )
+            .respond("rest/system/connections", counters(0))
+            .respond(
+                "rest/system/connections",
+                counters(1024 * 1024 * 1024 * 1024),
+            )
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+            )
+            .respond("rest/events", json!([])),
+    );
+    let module = Module::spawn(&server, &["--show-rate"]);
+
+    assert_eq!(
+        module.next_status()["tooltip"],
+        "<b>laptop</b>\n  Photos     40% (1 MiB, ETA: \u{2014})"
+    );
+    let tooltip = module.next_status()["tooltip"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert!(
+        tooltip.starts_with("<b>laptop</b>\n  Photos     40% (1 MiB, ")
+            && tooltip.ends_with("/s, ETA: 0s)"),
+        "{tooltip}"
+    );
+}
+
+#[test]
+fn rejected_api_key_is_named() {
+    let server = StubServer::start(Routes::new().respond_with(
+        "rest/system/status",
+        401,
+        "<html>Unauthorized</html>",
+    ));
+
+    let output = run(&server, &[]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            "Syncthing API returned 401 Unauthorized for rest/system/status, check your API key"
+        ),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn api_key_is_read_from_stdin() {
+    let server = StubServer::start(routes().respond(
+        "rest/events",
+        json!([folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+    ));
+    let mut module = Module::spawn_with_args(&["--api-key", "-", "--base-url", server.base_url()]);
+    module.send_line("  piped-api-key  ");
+    module.close_stdin();
+
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 40%/1 MiB");
+    assert_eq!(server.api_keys(), ["piped-api-key".to_string()].into());
+}
+
+#[test]
+fn api_key_from_stdin_is_shared_by_all_instances() {
+    let personal = StubServer::start(routes().respond(
+        "rest/events",
+        json!([folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+    ));
+    let work = StubServer::start(routes().respond(
+        "rest/events",
+        json!([folder_completion(1, PHONE, PHOTOS, 60., 2 * 1024 * 1024)]),
+    ));
+    let mut module = Module::spawn_with_args(&[
+        "--api-key",
+        "-",
+        "--base-url",
+        personal.base_url(),
+        "--base-url",
+        work.base_url(),
+    ]);
+    module.send_line("piped-api-key");
+    module.close_stdin();
+
+    let mut status = module.next_status();
+    while !status["tooltip"].as_str().unwrap().contains("phone") {
+        status = module.next_status();
+    }
+    assert_eq!(status["text"], "\u{f2f1} 50%/3 MiB");
+    assert_eq!(personal.api_keys(), ["piped-api-key".to_string()].into());
+    assert_eq!(work.api_keys(), ["piped-api-key".to_string()].into());
+}
+
+#[test]
+fn api_key_cmd_provides_the_api_key() {
+    let server = StubServer::start(routes().respond(
+        "rest/events",
+        json!([folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+    ));
+    let module = Module::spawn_with_args(&[
+        "--api-key-cmd",
+        "echo command-api-key",
+        "--base-url",
+        server.base_url(),
+    ]);
+
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 40%/1 MiB");
+    assert_eq!(server.api_keys(), ["command-api-key".to_string()].into());
+}
+
+#[test]
+fn failing_api_key_cmd_is_reported() {
+    let output = run_with_args(&["--api-key-cmd", "exit 4"]);
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("--api-key-cmd `exit 4` failed with exit status: 4"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn icon_and_idle_icon_are_configurable() {
+    let server = StubServer::start(
+        routes()
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+            )
+            .respond(
+                "rest/events",
+                json!([folder_completion(2, LAPTOP, PHOTOS, 100., 0)]),
+            ),
+    );
+    let module = Module::spawn(&server, &["--icon", "ST", "--idle-icon", "ST ok"]);
+
+    assert_eq!(module.next_status()["text"], "ST 40%/1 MiB");
+    assert_eq!(module.next_status()["text"], "ST ok");
+}
+
+#[test]
+fn cluster_view_is_ordered_by_folder_name() {
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                config(
+                    &[(LAPTOP, "laptop")],
+                    &[("a-id", "Zebra"), ("b-id", "apple"), ("c-id", "Mango")],
+                ),
+            )
+            .respond("rest/system/connections", connections(&[(LAPTOP, true)]))
+            .respond(
+                "rest/events",
+                json!([
+                    folder_completion(1, LAPTOP, "a-id", 10., 1024 * 1024),
+                    folder_completion(2, LAPTOP, "b-id", 20., 1024 * 1024),
+                    folder_completion(3, LAPTOP, "c-id", 30., 1024 * 1024),
+                ]),
+            ),
+    );
+    let module = Module::spawn(&server, &["--cluster-view"]);
+
+    let status = module.next_status();
+    assert_eq!(
+        status["text"],
+        "\u{f2f1} 20%/1 MiB | \u{f2f1} 30%/1 MiB | \u{f2f1} 10%/1 MiB"
+    );
+    assert_eq!(
+        status["tooltip"],
+        "apple: 1/1 peers, min 20%\nMango: 1/1 peers, min 30%\nZebra: 1/1 peers, min 10%"
+    );
+}
+
+#[test]
+fn paused_folder_is_dropped() {
+    let server = StubServer::start(
+        routes()
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+            )
+            .respond(
+                "rest/events",
+                json!([{
+                    "id": 2,
+                    "type": "FolderPaused",
+                    "data": { "id": PHOTOS, "label": "Photos" },
+                }]),
+            ),
+    );
+    let module = Module::spawn(&server, &[]);
+
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 40%/1 MiB");
+    assert_eq!(module.next_status()["text"], "");
+}
+
+#[test]
+fn config_file_sets_options_under_the_command_line() {
+    let server = StubServer::start(routes().respond(
+        "rest/events",
+        json!([folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+    ));
+    let home = std::env::temp_dir().join(format!("waybar-syncthing-config-{}", std::process::id()));
+    fs::create_dir_all(home.join("waybar-syncthing")).unwrap();
+    fs::write(
+        home.join("waybar-syncthing/config.toml"),
+        format!(
+            "# display preferences\n\
+             api_key = \"file-api-key\"\n\
+             base-url = \"{}\"\n\
+             icon = \"ST\"\n\
+             minimal = true\n",
+            server.base_url()
+        ),
+    )
+    .unwrap();
+
+    let module = Module::spawn_with_env(&["--icon", "CLI"], &[("XDG_CONFIG_HOME", &home)]);
+    let text = module.next_status()["text"].clone();
+    fs::remove_dir_all(&home).unwrap();
+
+    assert_eq!(text, "CLI 40%");
+    assert_eq!(server.api_keys(), ["file-api-key".to_string()].into());
+}
+
+#[test]
+fn include_and_exclude_filter_by_id_or_name() {
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                config(
+                    &[(LAPTOP, "laptop"), (PHONE, "phone")],
+                    &[(PHOTOS, "Photos"), ("music-id", "Music")],
+                ),
+            )
+            .respond(
+                "rest/system/connections",
+                connections(&[(LAPTOP, true), (PHONE, true)]),
+            )
+            .respond(
+                "rest/events",
+                json!([
+                    folder_completion(1, LAPTOP, PHOTOS, 10., 1024 * 1024),
+                    folder_completion(2, LAPTOP, "music-id", 20., 1024 * 1024),
+                    folder_completion(3, PHONE, PHOTOS, 30., 1024 * 1024),
+                ]),
+            ),
+    );
+    let module = Module::spawn(
+        &server,
+        &["--include-device", "laptop", "--exclude-folder", "music-id"],
+    );
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{f2f1} 10%/1 MiB");
+    assert_eq!(status["tooltip"], "<b>laptop</b>\n  Photos     10% (1 MiB)");
+}
+
+#[test]
+fn cached_names_spare_the_config_request() {
+    let cache = std::env::temp_dir().join(format!("waybar-syncthing-names-{}", std::process::id()));
+    let spawn = |server: &StubServer| {
+        Module::spawn_with_env(
+            &[
+                "--api-key",
+                "stub-api-key",
+                "--base-url",
+                server.base_url(),
+                "--cache-names",
+            ],
+            &[("XDG_CACHE_HOME", &cache)],
+        )
+    };
+    let events = json!([folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024)]);
+    let tooltip = "<b>laptop</b>\n  Photos     40% (1 MiB)";
+
+    // The cache belongs to the address, which the socket keeps across servers.
+    let socket = cache.with_extension("sock");
+    let first = StubServer::start_unix(routes().respond("rest/events", events.clone()), &socket);
+    let module = spawn(&first);
+    assert_eq!(module.next_status()["tooltip"], tooltip);
+    drop(module);
+    assert!(cache_file(&cache, "names", first.base_url()).exists());
+
+    let second = StubServer::start_unix(syncthing().respond("rest/events", events), &socket);
+    let module = spawn(&second);
+    assert_eq!(module.next_status()["tooltip"], tooltip);
+    assert!(!second
+        .requests()
+        .iter()
+        .any(|r| r.starts_with("/rest/system/config")));
+    drop(module);
+    fs::remove_dir_all(cache).unwrap();
+    fs::remove_file(socket).unwrap();
+}
+
+#[test]
+fn persisted_state_survives_a_restart() {
+    let cache = std::env::temp_dir().join(format!("waybar-syncthing-cache-{}", std::process::id()));
+    let spawn = |server: &StubServer| {
+        Module::spawn_with_env(
+            &[
+                "--api-key",
+                "stub-api-key",
+                "--base-url",
+                server.base_url(),
+                "--persist-state",
+            ],
+            &[("XDG_CACHE_HOME", &cache)],
+        )
+    };
+
+    let socket = cache.with_extension("sock");
+    let first = StubServer::start_unix(
+        routes()
+            .respond(
+                "rest/events",
+                json!([folder_completion(7, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+            )
+            .respond("rest/events", json!([])),
+        &socket,
+    );
+    let module = spawn(&first);
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 40%/1 MiB");
+    // The state is saved once the cycle that printed finishes.
+    let state = cache_file(&cache, "state", first.base_url());
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while !state.exists() && std::time::Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    drop(module);
+
+    let second = StubServer::start_unix(routes().respond("rest/events", json!([])), &socket);
+    let module = spawn(&second);
+    let text = module.next_status()["text"].clone();
+    drop(module);
+    fs::remove_dir_all(&cache).unwrap();
+    fs::remove_file(socket).unwrap();
+
+    assert_eq!(text, "\u{f2f1} 40%/1 MiB");
+    assert!(second
+        .requests()
+        .iter()
+        .any(|r| r.starts_with("/rest/events?since=7&")));
+}
+
+#[test]
+fn persisted_state_follows_the_instance_address() {
+    let cache =
+        std::env::temp_dir().join(format!("waybar-syncthing-reorder-{}", std::process::id()));
+    let spawn = |base_urls: &[&str]| {
+        let mut args = vec!["--api-key", "stub-api-key", "--persist-state"];
+        for base_url in base_urls {
+            args.extend(["--base-url", base_url]);
+        }
+        Module::spawn_with_env(&args, &[("XDG_CACHE_HOME", &cache)])
+    };
+
+    let personal = StubServer::start(
+        routes()
+            .respond(
+                "rest/events",
+                json!([folder_completion(7, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+            )
+            .respond("rest/events", json!([])),
+    );
+    let work = StubServer::start(routes().respond("rest/events", json!([])));
+    let module = spawn(&[personal.base_url()]);
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 40%/1 MiB");
+    let state = cache_file(&cache, "state", personal.base_url());
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while !state.exists() && std::time::Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    drop(module);
+
+    // Another instance in front of it must not pick up its state.
+    let module = spawn(&[work.base_url(), personal.base_url()]);
+    let tooltip = module.next_status()["tooltip"].clone();
+    drop(module);
+    fs::remove_dir_all(&cache).unwrap();
+
+    let tooltip = tooltip.as_str().unwrap();
+    let label = personal.base_url().trim_start_matches("http://");
+    assert!(tooltip.starts_with(&format!("{label}\n")), "{tooltip}");
+    assert!(tooltip.ends_with("40% (1 MiB)"), "{tooltip}");
+}
+
+#[test]
+fn sigterm_clears_the_bar() {
+    let server = StubServer::start(
+        routes()
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+            )
+            .respond_after("rest/events", Duration::from_secs(30), json!([])),
+    );
+    let mut module = Module::spawn(&server, &[]);
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 40%/1 MiB");
+
+    assert!(module.terminate().success());
+    assert_eq!(
+        module.next_status(),
+        json!({ "text": "", "tooltip": "", "class": "idle" })
+    );
+}
+
+#[test]
+fn plain_output_prints_only_the_text() {
+    let server = StubServer::start(routes().respond(
+        "rest/events",
+        json!([folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+    ));
+    let module = Module::spawn(&server, &["--output-format", "plain"]);
+
+    assert_eq!(module.next_line(), "\u{f2f1} 40%/1 MiB");
+}
+
+#[test]
+fn templates_format_text_and_tooltip() {
+    let server = StubServer::start(routes().respond(
+        "rest/events",
+        json!([
+            with_global_bytes(
+                folder_completion(1, LAPTOP, PHOTOS, 50., 2 * 1024 * 1024),
+                4 * 1024 * 1024
+            ),
+            with_global_bytes(
+                folder_completion(2, PHONE, PHOTOS, 75., 1024 * 1024),
+                4 * 1024 * 1024
+            ),
+        ]),
+    ));
+    let module = Module::spawn(
+        &server,
+        &[
+            "--text-format",
+            "{device} {percent}%",
+            "--tooltip-format",
+            "{folder} on {device}: {need_bytes} of {total_bytes} {{left}}",
+        ],
+    );
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "laptop 50% | phone 75%");
+    assert_eq!(
+        status["tooltip"],
+        "Photos on laptop: 2 MiB of 4 MiB {left}\nPhotos on phone: 1 MiB of 4 MiB {left}"
+    );
+}
+
+#[test]
+fn aggregate_mode_weights_by_size_or_averages() {
+    const MIB: u64 = 1024 * 1024;
+    let events = json!([
+        with_global_bytes(folder_completion(1, LAPTOP, PHOTOS, 99., MIB), 100 * MIB),
+        with_global_bytes(folder_completion(2, LAPTOP, "music-id", 10., 900), 1000),
+    ]);
+
+    for (mode, percent) in [("weighted", 99), ("mean", 55)] {
+        let server = StubServer::start(routes().respond("rest/events", events.clone()));
+        let module = Module::spawn(&server, &["--minimal", "--aggregate", mode]);
+        assert_eq!(
+            module.next_status()["text"],
+            format!("\u{f2f1} {percent}%"),
+            "{mode}"
+        );
+    }
+}
+
+#[test]
+fn aggregate_format_counts_pending_folders() {
+    let server = StubServer::start(routes().respond(
+        "rest/events",
+        json!([
+            with_global_bytes(
+                folder_completion(1, LAPTOP, PHOTOS, 50., 2 * 1024 * 1024),
+                4 * 1024 * 1024
+            ),
+            with_global_bytes(
+                folder_completion(2, PHONE, PHOTOS, 75., 1024 * 1024),
+                4 * 1024 * 1024
+            ),
+        ]),
+    ));
+    let module = Module::spawn(
+        &server,
+        &["--aggregate-format", "{icon} {percent}% of {count}"],
+    );
+
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 63% of 2");
+}
+
+#[test]
+fn version_names_the_build_and_endpoints() {
+    let output = run_with_args(&["--version"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines = stdout.lines();
+    let version = lines.next().unwrap();
+    assert!(
+        version.starts_with(&format!("waybar-syncthing {} (", env!("CARGO_PKG_VERSION")))
+            && version.contains(", built 20"),
+        "{version}"
+    );
+    assert!(
+        lines.next().unwrap().contains("rest/db/completion"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn unknown_placeholder_fails_at_startup() {
+    let output = run_with_args(&["--text-format", "{percnt}%"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("unknown placeholder {percnt}"), "{stderr}");
+
+    let output = run_with_args(&["--aggregate-format", "{device}"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn local_scan_and_pull_progress_is_shown() {
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                config(
+                    &[("LOCAL-ID", "desktop"), (LAPTOP, "laptop")],
+                    &[(PHOTOS, "Photos")],
+                ),
+            )
+            .respond("rest/system/connections", connections(&[(LAPTOP, true)]))
+            .respond(
+                "rest/events",
+                json!([folder_summary(1, PHOTOS, "scanning", 0)]),
+            )
+            .respond(
+                "rest/events",
+                json!([folder_summary(2, PHOTOS, "syncing", 1024 * 1024)]),
+            )
+            .respond("rest/events", json!([folder_summary(3, PHOTOS, "idle", 0)])),
+    );
+    let module = Module::spawn(&server, &["--local-progress"]);
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{f2f1} scanning");
+    assert_eq!(status["tooltip"], "<b>desktop</b>\n  Photos     scanning");
+    assert_eq!(status["class"], "syncing");
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{f2f1} 75%/1 MiB");
+    assert_eq!(
+        status["tooltip"],
+        "<b>desktop</b>\n  Photos     75% (1 MiB)"
+    );
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "");
+    assert_eq!(status["class"], "idle");
+    assert!(server
+        .requests()
+        .iter()
+        .any(|r| r.contains("FolderSummary")));
+}
+
+#[test]
+fn scanning_folder_sets_class_until_idle() {
+    let server = StubServer::start(
+        routes()
+            .respond("rest/events", json!([state_changed(1, PHOTOS, "scanning")]))
+            .respond("rest/events", json!([state_changed(2, PHOTOS, "idle")])),
+    );
+    let module = Module::spawn(&server, &["--show-scanning", "--scanning-icon", "S"]);
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "S");
+    assert_eq!(status["class"], "scanning");
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "");
+    assert_eq!(status["class"], "idle");
+}
+
+#[test]
+fn folder_errors_are_shown_until_completion() {
+    let server = StubServer::start(
+        routes()
+            .respond(
+                "rest/events",
+                json!([
+                    folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024),
+                    {
+                        "id": 2,
+                        "type": "FolderErrors",
+                        "data": {
+                            "folder": PHOTOS,
+                            "errors": [
+                                { "error": "permission denied", "path": "a.jpg" },
+                                { "error": "permission denied", "path": "b.jpg" },
+                            ],
+                        },
+                    },
+                ]),
+            )
+            .respond(
+                "rest/events",
+                json!([folder_completion(3, LAPTOP, PHOTOS, 100., 0)]),
+            ),
+    );
+    let module = Module::spawn(&server, &[]);
+
+    let status = module.next_status();
+    assert_eq!(
+        status["tooltip"],
+        "<b>laptop</b>\n  Photos     40% (1 MiB) \u{26a0} a.jpg: permission denied (+1 more)"
+    );
+    assert_eq!(status["class"], "error");
+
+    let status = module.next_status();
+    assert_eq!(status["tooltip"], "");
+    assert_eq!(status["class"], "idle");
+}
+
+#[test]
+fn combine_instances_label_places_the_instance_address() {
+    let tooltip = |layout: &str| {
+        let personal = StubServer::start(routes().respond(
+            "rest/events",
+            json!([folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+        ));
+        let work = StubServer::start(routes().respond(
+            "rest/events",
+            json!([folder_completion(1, PHONE, PHOTOS, 60., 2 * 1024 * 1024)]),
+        ));
+        let module = Module::spawn_with_args(&[
+            "--base-url",
+            personal.base_url(),
+            "--base-url",
+            work.base_url(),
+            "--api-key",
+            "stub-api-key",
+            "--combine-instances-label",
+            layout,
+        ]);
+
+        let mut status = module.next_status();
+        while !status["tooltip"].as_str().unwrap().contains("phone") {
+            status = module.next_status();
+        }
+        let label =
+            |server: &StubServer| server.base_url().trim_start_matches("http://").to_string();
+        (
+            status["tooltip"].as_str().unwrap().to_owned(),
+            label(&personal),
+            label(&work),
+        )
+    };
+
+    let (grouped, personal, work) = tooltip("grouped");
+    assert_eq!(
+        grouped,
+        format!("{personal}\n  <b>laptop</b>\n    Photos     40% (1 MiB)\n{work}\n  <b>phone</b>\n    Photos     60% (2 MiB)")
+    );
+    let (prefix, personal, work) = tooltip("prefix");
+    assert_eq!(
+        prefix,
+        format!("{personal}: <b>laptop</b>\n  Photos     40% (1 MiB)\n{work}: <b>phone</b>\n  Photos     60% (2 MiB)")
+    );
+    let (suffix, personal, work) = tooltip("suffix");
+    assert_eq!(
+        suffix,
+        format!("<b>laptop</b> ({personal})\n  Photos     40% (1 MiB)\n<b>phone</b> ({work})\n  Photos     60% (2 MiB)")
+    );
+}
+
+#[test]
+fn several_instances_are_shown_as_one_module() {
+    let personal = StubServer::start(routes().respond(
+        "rest/events",
+        json!([folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+    ));
+    let work = StubServer::start(routes().respond(
+        "rest/events",
+        json!([folder_completion(1, PHONE, PHOTOS, 60., 2 * 1024 * 1024)]),
+    ));
+    let module = Module::spawn_with_args(&[
+        "--base-url",
+        personal.base_url(),
+        "--api-key",
+        "personal-key",
+        "--base-url",
+        work.base_url(),
+        "--api-key",
+        "work-key",
+    ]);
+
+    let mut status = module.next_status();
+    while !status["tooltip"].as_str().unwrap().contains("phone") {
+        status = module.next_status();
+    }
+    let label = |server: &StubServer| server.base_url().trim_start_matches("http://").to_string();
+    assert_eq!(status["text"], "\u{f2f1} 50%/3 MiB");
+    assert_eq!(
+        status["tooltip"],
+        format!(
+            "{}\n  <b>laptop</b>\n    Photos     40% (1 MiB)\n{}\n  <b>phone</b>\n    Photos     60% (2 MiB)",
+            label(&personal),
+            label(&work)
+        )
+    );
+    assert!(work.api_keys().contains("work-key"));
+    assert!(!work.api_keys().contains("personal-key"));
+}
+
+#[test]
+fn api_keys_have_to_match_base_urls() {
+    let output = run_with_args(&[
+        "--base-url",
+        "http://localhost:1",
+        "--base-url",
+        "http://localhost:2",
+        "--api-key",
+        "a",
+        "--api-key",
+        "b",
+    ]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("once for every --base-url"), "{stderr}");
+}
+
+#[test]
+fn base_url_is_normalized() {
+    let server = StubServer::start(routes());
+    let expected = run(&server, &["--once"]).stdout;
+
+    let with_slash = format!("{}/", server.base_url());
+    let bare = server.base_url().trim_start_matches("http://");
+    for base_url in [with_slash.as_str(), bare] {
+        let output = run_with_args(&["--base-url", base_url, "--once"]);
+        assert!(output.status.success(), "{base_url}");
+        assert_eq!(output.stdout, expected, "{base_url}");
+    }
+
+    let output = run_with_args(&["--base-url", "ftp://localhost:8384", "--once"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("expected an http://, https:// or unix:// address"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn unix_socket_is_supported() {
+    let socket = std::env::temp_dir().join(format!("waybar-syncthing-{}.sock", std::process::id()));
+    let server = StubServer::start_unix(
+        routes().respond(
+            "rest/events",
+            json!([folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+        ),
+        &socket,
+    );
+    let module = Module::spawn(&server, &[]);
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{f2f1} 40%/1 MiB");
+    assert_eq!(status["tooltip"], "<b>laptop</b>\n  Photos     40% (1 MiB)");
+    assert!(server.api_keys().contains("stub-api-key"));
+    drop(module);
+    fs::remove_file(socket).unwrap();
+}
+
+#[test]
+fn verbose_logs_requests_to_stderr() {
+    let server = StubServer::start(routes());
+
+    let output = run(&server, &["--once"]);
+    assert!(output.stderr.is_empty());
+
+    let output = run(&server, &["--once", "-vv"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!(
+            "DEBUG GET {}/rest/system/status",
+            server.base_url()
+        )),
+        "{stderr}"
+    );
+    assert!(stderr.contains("INFO Watching Syncthing at"), "{stderr}");
+}
+
+#[test]
+fn names_are_escaped_for_markup() {
+    let routes = || {
+        syncthing()
+            .respond(
+                "rest/system/config",
+                config(&[(LAPTOP, "R&D <lab>")], &[(PHOTOS, "Tom's")]),
+            )
+            .respond("rest/system/connections", connections(&[(LAPTOP, true)]))
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+            )
+    };
+
+    let server = StubServer::start(routes());
+    let module = Module::spawn(&server, &[]);
+    assert_eq!(
+        module.next_status()["tooltip"],
+        "<b>R&amp;D &lt;lab&gt;</b>\n  Tom&#39;s      40% (1 MiB)"
+    );
+
+    let server = StubServer::start(routes());
+    let module = Module::spawn(&server, &["--no-markup"]);
+    assert_eq!(
+        module.next_status()["tooltip"],
+        "R&D <lab>\n  Tom's      40% (1 MiB)"
+    );
+}
+
+#[test]
+fn connected_devices_are_counted() {
+    let server = StubServer::start(
+        routes()
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+            )
+            .respond(
+                "rest/events",
+                json!([folder_completion(2, LAPTOP, PHOTOS, 100., 0)]),
+            ),
+    );
+    let module = Module::spawn(&server, &["--show-connected"]);
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{f2f1} 40%/1 MiB | 2/2 online");
+    assert_eq!(
+        status["tooltip"],
+        "2/2 devices connected\n<b>laptop</b>\n  Photos     40% (1 MiB)"
+    );
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{f2f1}");
+    assert_eq!(status["tooltip"], "2/2 devices connected");
+}
+
+#[test]
+fn offline_devices_show_in_the_count_when_idle() {
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                config(&[(LAPTOP, "laptop"), (PHONE, "phone")], &[]),
+            )
+            .respond(
+                "rest/system/connections",
+                connections(&[(LAPTOP, true), (PHONE, false)]),
+            )
+            .respond("rest/events", json!([device_disconnected(1, PHONE)])),
+    );
+    let module = Module::spawn(&server, &["--show-connected"]);
+
+    assert_eq!(module.next_status()["text"], "1/2 online");
+}
+
+#[test]
+fn hanging_long_poll_times_out_and_reconnects() {
+    let server = StubServer::start(
+        routes()
+            .respond_after("rest/events", Duration::from_secs(6), json!([]))
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+            ),
+    );
+    let module = Module::spawn(
+        &server,
+        &["--poll-events-max-wait", "1", "--timeout-secs", "1"],
+    );
+
+    assert_eq!(module.next_status()["class"], "disconnected");
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 40%/1 MiB");
+}
+
+#[test]
+fn local_mode_shows_this_devices_completion() {
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                config(&[("LOCAL-ID", "desktop"), (LAPTOP, "laptop")], &[]),
+            )
+            .respond(
+                "rest/db/completion",
+                json!({ "completion": 95., "needBytes": 1024 * 1024, "globalBytes": 20 * 1024 * 1024 }),
+            ),
+    );
+    let module = Module::spawn(&server, &["--mode", "local"]);
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{f2f1} 95%/1 MiB");
+    assert_eq!(
+        status["tooltip"],
+        "<b>desktop</b>\n  All folders 95% (1 MiB)"
+    );
+    assert_eq!(status["class"], "syncing");
+    assert!(server.requests().iter().any(|r| r == "/rest/db/completion"));
+    assert!(!server.requests().iter().any(|r| r.contains("rest/events")));
+}
+
+#[test]
+fn class_thresholds_tier_the_syncing_class() {
+    let server = StubServer::start(
+        routes()
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 20., 1024 * 1024)]),
+            )
+            .respond(
+                "rest/events",
+                json!([folder_completion(2, LAPTOP, PHOTOS, 50., 1024 * 1024)]),
+            )
+            .respond(
+                "rest/events",
+                json!([folder_completion(3, LAPTOP, PHOTOS, 80., 1024 * 1024)]),
+            )
+            .respond(
+                "rest/events",
+                json!([folder_completion(4, LAPTOP, PHOTOS, 100., 0)]),
+            ),
+    );
+    let module = Module::spawn(&server, &["--class-thresholds", "33,66"]);
+
+    assert_eq!(module.next_status()["class"], "syncing-low");
+    assert_eq!(module.next_status()["class"], "syncing-mid");
+    assert_eq!(module.next_status()["class"], "syncing-high");
+    assert_eq!(module.next_status()["class"], "idle");
+}
+
+#[test]
+fn class_thresholds_are_validated() {
+    for thresholds in ["66,33", "33,120", "33"] {
+        let output = run_with_args(&["--class-thresholds", thresholds]);
+        assert!(!output.status.success(), "{thresholds}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("--class-thresholds"), "{stderr}");
+    }
+}
+
+#[test]
+fn idle_format_tells_when_the_last_folder_synced() {
+    let server = StubServer::start(
+        routes()
+            .respond(
+                "rest/events",
+                json!([folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+            )
+            .respond(
+                "rest/events",
+                json!([folder_completion(2, LAPTOP, PHOTOS, 100., 0)]),
+            ),
+    );
+    let module = Module::spawn(&server, &["--idle-format", "✓ synced {ago}"]);
+
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 40%/1 MiB");
+    let status = module.next_status();
+    assert_eq!(status["text"], "✓ synced just now");
+    assert_eq!(status["class"], "idle");
+}
+
+#[test]
+fn idle_format_leaves_out_an_unseen_sync() {
+    let server = StubServer::start(routes().respond("rest/events", json!([])));
+    let module = Module::spawn(&server, &["--idle-format", "✓ synced {ago}"]);
+
+    assert_eq!(module.next_status()["text"], "✓ synced");
+}
+
+#[test]
+fn idle_format_remembers_the_last_sync_across_restarts() {
+    let cache = std::env::temp_dir().join(format!("waybar-syncthing-idle-{}", std::process::id()));
+    let server = StubServer::start(routes().respond("rest/events", json!([])));
+    let state = cache_file(&cache, "state", server.base_url());
+    fs::create_dir_all(state.parent().unwrap()).unwrap();
+    let synced_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        - 200;
+    fs::write(
+        &state,
+        json!({ "since": 0, "startTime": STARTED, "lastSynced": synced_at, "pending": {} })
+            .to_string(),
+    )
+    .unwrap();
+
+    let module = Module::spawn_with_env(
+        &[
+            "--api-key",
+            "stub-api-key",
+            "--base-url",
+            server.base_url(),
+            "--persist-state",
+            "--idle-format",
+            "✓ synced {ago}",
+        ],
+        &[("XDG_CACHE_HOME", &cache)],
+    );
+    let text = module.next_status()["text"].clone();
+    drop(module);
+    fs::remove_dir_all(&cache).unwrap();
+
+    assert_eq!(text, "✓ synced 3m ago");
+}
+
+#[test]
+fn ago_is_only_accepted_in_idle_format() {
+    let output = run_with_args(&["--text-format", "{ago}"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("only available in --idle-format"),
+        "{stderr}"
+    );
+
+    let output = run_with_args(&["--idle-format", "{percent}"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn poll_interval_replaces_events_with_completion_polls() {
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                json!({
+                    "devices": [{ "deviceID": LAPTOP, "name": "laptop" }],
+                    "folders": [
+                        { "id": PHOTOS, "label": "Photos", "devices": [{ "deviceID": LAPTOP }] },
+                    ],
+                }),
+            )
+            .respond("rest/system/connections", connections(&[(LAPTOP, true)]))
+            .respond(
+                "rest/db/completion",
+                json!({ "completion": 40., "needBytes": 1024 * 1024, "globalBytes": 2 * 1024 * 1024 }),
+            )
+            .respond(
+                "rest/db/completion",
+                json!({ "completion": 100., "needBytes": 0, "globalBytes": 2 * 1024 * 1024 }),
+            ),
+    );
+    let module = Module::spawn(&server, &["--poll-interval", "1"]);
+
+    assert_eq!(module.next_status()["text"], "\u{f2f1} 40%/1 MiB");
+    assert_eq!(module.next_status()["class"], "idle");
+    assert!(server
+        .requests()
+        .iter()
+        .any(|r| r == &format!("/rest/db/completion?device={LAPTOP}&folder={PHOTOS}")));
+    assert!(!server.requests().iter().any(|r| r.contains("rest/events")));
+}
+
+#[test]
+fn no_icons_falls_back_to_a_label() {
+    let routes = || {
+        routes().respond(
+            "rest/events",
+            json!([folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024)]),
+        )
+    };
+
+    let server = StubServer::start(routes());
+    let module = Module::spawn(&server, &["--no-icons"]);
+    assert_eq!(module.next_status()["text"], "Syncthing: 40%/1 MiB");
+
+    let server = StubServer::start(routes());
+    let module = Module::spawn_with_env(
+        &["--api-key", "stub-api-key", "--base-url", server.base_url()],
+        &[("SYNCTHING_NO_ICONS", std::path::Path::new("1"))],
+    );
+    assert_eq!(module.next_status()["text"], "Syncthing: 40%/1 MiB");
+}
+
+#[test]
+fn pending_approvals_need_attention_until_accepted() {
+    const TABLET: &str = "TABLET-ID";
+    let server = StubServer::start(
+        syncthing()
+            .respond(
+                "rest/system/config",
+                config(&[(LAPTOP, "laptop")], &[(PHOTOS, "Photos")]),
+            )
+            .respond(
+                "rest/system/config",
+                config(&[(LAPTOP, "laptop"), (TABLET, "tablet")], &[(PHOTOS, "Photos")]),
+            )
+            .respond(
+                "rest/events",
+                json!([
+                    { "id": 1, "type": "DeviceRejected", "data": { "device": TABLET, "name": "tablet", "address": "192.0.2.2:22000" } },
+                    { "id": 2, "type": "FolderRejected", "data": { "device": LAPTOP, "folder": "docs-id", "folderLabel": "Docs" } },
+                ]),
+            )
+            .respond(
+                "rest/events",
+                json!([{ "id": 3, "type": "ConfigSaved", "data": { "version": 37 } }]),
+            ),
+    );
+    let module = Module::spawn(&server, &["--show-pending-approvals"]);
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{26a0} 2");
+    assert_eq!(
+        status["tooltip"],
+        "<b>Awaiting approval</b>\n  tablet wants to connect\n  laptop shares Docs"
+    );
+    assert_eq!(status["class"], "attention");
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{26a0} 1");
+    assert_eq!(
+        status["tooltip"],
+        "<b>Awaiting approval</b>\n  laptop shares Docs"
+    );
+    assert!(server.requests().iter().any(|r| r.contains(
+        "events=FolderCompletion,DeviceDisconnected,DeviceConnected,FolderPaused,FolderErrors,ConfigSaved,DeviceRejected,FolderRejected,PendingDevicesChanged"
+    )));
+}
+
+#[test]
+fn pending_approvals_survive_the_rolled_up_text() {
+    let server = StubServer::start(routes().respond(
+        "rest/events",
+        json!([
+            folder_completion(1, LAPTOP, PHOTOS, 40., 1024 * 1024),
+            { "id": 2, "type": "DeviceRejected", "data": { "device": "TABLET-ID", "name": "tablet", "address": "192.0.2.2:22000" } },
+        ]),
+    ));
+    let module = Module::spawn(&server, &["--show-pending-approvals", "--progress-bar"]);
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{f2f1} 40%/1 MiB | \u{26a0} 1");
+    assert_eq!(
+        status["tooltip"],
+        "<b>laptop</b>\n  Photos     40% (1 MiB)\n<b>Awaiting approval</b>\n  tablet wants to connect"
+    );
+}
+
+#[test]
+fn pending_devices_changed_adds_and_removes_approvals() {
+    let server = StubServer::start(
+        routes()
+            .respond(
+                "rest/events",
+                json!([{ "id": 1, "type": "PendingDevicesChanged", "data": {
+                    "added": [{ "deviceID": "TABLET-ID", "name": "", "address": "192.0.2.2:22000" }],
+                } }]),
+            )
+            .respond(
+                "rest/events",
+                json!([{ "id": 2, "type": "PendingDevicesChanged", "data": {
+                    "removed": [{ "deviceID": "TABLET-ID" }],
+                } }]),
+            ),
+    );
+    let module = Module::spawn(&server, &["--show-pending-approvals"]);
+
+    let status = module.next_status();
+    assert_eq!(status["text"], "\u{26a0} 1");
+    assert_eq!(
+        status["tooltip"],
+        "<b>Awaiting approval</b>\n  TABLET wants to connect"
+    );
+    assert_eq!(
+        module.next_status(),
+        json!({ "text": "", "tooltip": "", "class": "idle" })
+    );
+}
+
+#[test]
+fn mean_aggregate_averages_folders_across_instances() {
+    let personal = StubServer::start(routes().respond(
+        "rest/events",
+        json!([
+            folder_completion(1, LAPTOP, PHOTOS, 90., 1024),
+            folder_completion(2, LAPTOP, "music-id", 10., 1024),
+        ]),
+    ));
+    let work = StubServer::start(routes().respond(
+        "rest/events",
+        json!([folder_completion(1, PHONE, PHOTOS, 80., 1024)]),
+    ));
+    let module = Module::spawn_with_args(&[
+        "--base-url",
+        personal.base_url(),
+        "--base-url",
+        work.base_url(),
+        "--api-key",
+        "stub-api-key",
+        "--minimal",
+        "--aggregate",
+        "mean",
+    ]);
+
+    let mut status = module.next_status();
+    // Until both instances reported, one of them alone is shown.
+    while ["\u{f2f1} 50%", "\u{f2f1} 80%"].contains(&status["text"].as_str().unwrap()) {
+        status = module.next_status();
+    }
+    // Each folder counts once, not each instance.
+    assert_eq!(status["text"], "\u{f2f1} 60%");
+}